@@ -0,0 +1,196 @@
+use chrono::prelude::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::OnceLock;
+use std::thread::spawn;
+
+/// Something that happened while synchronizing a mailbox, for the
+/// activity stream. Flags are Maildir flags, like "FS".
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Activity {
+    /// A sync pass started.
+    SyncStart,
+    /// A sync pass finished, successfully or not.
+    SyncEnd { ok: bool, duration_ms: u64 },
+    /// A message was downloaded from the server into the Maildir.
+    Download {
+        uid: u32,
+        id: String,
+        size: u32,
+        flags: String,
+        duration_ms: u64,
+    },
+    /// A message was deleted from the Maildir, because it was deleted
+    /// on the server.
+    LocalDelete { uid: u32, id: String },
+    /// A message was deleted from the server, because it was deleted
+    /// from the Maildir.
+    ServerDelete { uid: u32, id: String },
+    /// The flags of a message in the Maildir were changed to match the
+    /// server.
+    LocalFlags {
+        uid: u32,
+        id: String,
+        from: String,
+        to: String,
+    },
+    /// The flags of a message on the server were changed to match the
+    /// Maildir.
+    ServerFlags {
+        uid: u32,
+        id: String,
+        from: String,
+        to: String,
+    },
+    /// A new message in the Maildir was uploaded to the server.
+    Append {
+        id: String,
+        size: u64,
+        flags: String,
+    },
+    /// Something went wrong.
+    Error { message: String },
+}
+
+/// A line of the activity stream.
+#[derive(Serialize)]
+struct Record<'a> {
+    time: String,
+    account: &'a str,
+    mailbox: &'a str,
+    #[serde(flatten)]
+    activity: Activity,
+}
+
+/// What the writer thread is asked to do.
+enum Message {
+    Line(String),
+    /// Reply when everything before this has been written.
+    Flush(Sender<()>),
+}
+
+/// Messages for the writer thread, if there is an activity stream.
+static WRITER: OnceLock<Sender<Message>> = OnceLock::new();
+
+/// Where the activity stream goes.
+enum Output {
+    Stdout,
+    File(File),
+    /// A named pipe, which is open while it has a reader.
+    Fifo {
+        path: PathBuf,
+        file: Option<File>,
+    },
+}
+
+impl Output {
+    fn open(output: &str) -> Result<Output, String> {
+        if output == "-" {
+            return Ok(Output::Stdout);
+        }
+        let path = PathBuf::from(output);
+        if fs::metadata(&path).is_ok_and(|m| m.file_type().is_fifo()) {
+            return Ok(Output::Fifo { path, file: None });
+        }
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map(Output::File)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    /// Write a line in a single write, so that readers never see half
+    /// a line.
+    fn write(&mut self, line: &str) {
+        match self {
+            Output::Stdout => {
+                io::stdout().lock().write_all(line.as_bytes()).ok();
+            }
+            Output::File(file) => {
+                file.write_all(line.as_bytes()).ok();
+            }
+            Output::Fifo { path, file } => {
+                if file.is_none() {
+                    *file = open_fifo(path);
+                }
+                // If the reader has gone, wait for another one
+                if let Some(pipe) = file {
+                    if pipe.write_all(line.as_bytes()).is_err() {
+                        *file = None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Open a named pipe for writing if it has a reader, without waiting
+/// for one.
+fn open_fifo(path: &PathBuf) -> Option<File> {
+    let file = OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+        .ok()?;
+    // Writes block again, so a slow reader does not lose events
+    unsafe {
+        let flags = libc::fcntl(file.as_raw_fd(), libc::F_GETFL);
+        libc::fcntl(file.as_raw_fd(), libc::F_SETFL, flags & !libc::O_NONBLOCK);
+    }
+    Some(file)
+}
+
+/// Write the activity stream to `output`, which is "-" for standard
+/// output, a named pipe, or a file to append to. A thread does the
+/// writing, so that a slow reader never holds up synchronization. Call
+/// `signals::handle` first, so that the thread leaves signals alone.
+pub fn init(output: &str) -> Result<(), String> {
+    let mut output = Output::open(output)?;
+    let (writer, messages) = channel();
+    WRITER
+        .set(writer)
+        .map_err(|_| "The activity stream is already set up".to_string())?;
+    spawn(move || {
+        for message in messages {
+            match message {
+                Message::Line(line) => output.write(&line),
+                Message::Flush(done) => {
+                    done.send(()).ok();
+                }
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Wait until everything in the stream so far has been written, before
+/// we exit.
+pub fn flush() {
+    if let Some(writer) = WRITER.get() {
+        let (done, wait) = channel();
+        if writer.send(Message::Flush(done)).is_ok() {
+            wait.recv().ok();
+        }
+    }
+}
+
+/// Add an activity of a mailbox to the stream, if there is one.
+pub fn emit(account: &str, mailbox: &str, activity: Activity) {
+    if let Some(writer) = WRITER.get() {
+        let record = Record {
+            time: Local::now().to_rfc3339_opts(SecondsFormat::Millis, false),
+            account,
+            mailbox,
+            activity,
+        };
+        if let Ok(line) = serde_json::to_string(&record) {
+            writer.send(Message::Line(line + "\n")).ok();
+        }
+    }
+}
@@ -1,29 +1,33 @@
 use crate::cache::messagemeta::MessageMeta;
-use rusqlite::{params, Connection};
+use rusqlite::{params, Connection, OpenFlags};
 use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 
 pub struct Db {
     dbpath: PathBuf,
+    read_only: bool,
+}
+
+/// Create the table of messages.
+fn create_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE v1 (
+            uid                     INTEGER PRIMARY KEY,
+            size                    INTEGER,
+            internal_date_millis    INTEGER,
+            flags                   TEXT,
+            id                      TEXT
+        )",
+        params![],
+    )
+    .map(|_| ())
 }
 
 impl Db {
     fn init_db(path: &Path) -> Result<(), String> {
         let conn = Connection::open(path)
             .map_err(|e| format!("DB Open failed at {}: {}", path.display(), e))?;
-
-        conn.execute(
-            "CREATE TABLE v1 (
-                uid                     INTEGER PRIMARY KEY,
-                size                    INTEGER,
-                internal_date_millis    INTEGER,
-                flags                   TEXT,
-                id                      TEXT
-            )",
-            params![],
-        )
-        .map(|_| ())
-        .map_err(|e| format!("CREATE TABLE: {}", e))
+        create_table(&conn).map_err(|e| format!("CREATE TABLE: {}", e))
     }
 
     pub fn from_file(path: &Path) -> Result<Db, String> {
@@ -32,11 +36,34 @@ impl Db {
         }
         Ok(Db {
             dbpath: path.to_path_buf(),
+            read_only: false,
         })
     }
 
+    /// Use the db file at the given path without changing it, or
+    /// creating it if it does not exist.
+    pub fn read_only(path: &Path) -> Db {
+        Db {
+            dbpath: path.to_path_buf(),
+            read_only: true,
+        }
+    }
+
+    fn open(&self) -> rusqlite::Result<Connection> {
+        if !self.read_only {
+            return Connection::open(&self.dbpath);
+        }
+        if self.dbpath.exists() {
+            return Connection::open_with_flags(&self.dbpath, OpenFlags::SQLITE_OPEN_READ_ONLY);
+        }
+        // A db that does not exist yet is empty
+        let conn = Connection::open_in_memory()?;
+        create_table(&conn)?;
+        Ok(conn)
+    }
+
     pub fn add(&self, meta: &MessageMeta) -> Result<(), String> {
-        Connection::open(&self.dbpath)
+        self.open()
             .and_then(|conn| {
                 conn.execute(
                     "INSERT INTO v1 (uid, size, internal_date_millis, flags, id)
@@ -55,7 +82,7 @@ impl Db {
     }
 
     pub fn update(&self, meta: &MessageMeta) -> Result<(), String> {
-        Connection::open(&self.dbpath)
+        self.open()
             .and_then(|conn| {
                 conn.execute(
                     "UPDATE v1 SET uid = (?1),
@@ -78,14 +105,14 @@ impl Db {
     }
 
     pub fn delete_uid(&self, uid: u32) -> Result<(), String> {
-        Connection::open(&self.dbpath)
+        self.open()
             .and_then(|conn| conn.execute("DELETE from v1 WHERE uid = (?1)", params![uid]))
             .map(|_| ())
             .map_err(|e| format!("DELETE FAILED {}: {}", uid, e))
     }
 
     pub fn num_entries(&self) -> Result<i64, String> {
-        let conn = Connection::open(&self.dbpath).map_err(|e| format!("Open DB: {}", e))?;
+        let conn = self.open().map_err(|e| format!("Open DB: {}", e))?;
         let mut stmt = conn
             .prepare("SELECT count(uid) from v1")
             .map_err(|e| format!("SELECT: {}", e))?;
@@ -104,7 +131,7 @@ impl Db {
 
     pub fn get_uids(&self) -> Result<HashSet<u32>, String> {
         let mut v = HashSet::with_capacity(self.expected_entries());
-        let conn = Connection::open(&self.dbpath).map_err(|e| format!("Open DB: {}", e))?;
+        let conn = self.open().map_err(|e| format!("Open DB: {}", e))?;
 
         let mut stmt = conn
             .prepare("SELECT uid FROM v1")
@@ -121,7 +148,7 @@ impl Db {
     }
 
     pub fn get_ids(&self) -> Result<HashMap<String, MessageMeta>, String> {
-        let conn = Connection::open(&self.dbpath).map_err(|e| format!("Open DB: {}", e))?;
+        let conn = self.open().map_err(|e| format!("Open DB: {}", e))?;
 
         let mut stmt = conn
             .prepare("SELECT uid, size, internal_date_millis, flags, id FROM v1")
@@ -147,7 +174,7 @@ impl Db {
     }
 
     pub fn get_uid(&self, uid: u32) -> anyhow::Result<MessageMeta> {
-        let conn = Connection::open(&self.dbpath)?;
+        let conn = self.open()?;
 
         let mut stmt = conn.prepare(
             "SELECT uid, size, internal_date_millis, flags, id
@@ -167,7 +194,7 @@ impl Db {
     }
 
     pub fn get_id(&self, id: &str) -> Result<MessageMeta, String> {
-        let conn = Connection::open(&self.dbpath).map_err(|e| format!("Open DB: {}", e))?;
+        let conn = self.open().map_err(|e| format!("Open DB: {}", e))?;
 
         let mut stmt = conn
             .prepare(
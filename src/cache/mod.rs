@@ -7,11 +7,10 @@ use self::db::Db;
 pub use self::messagemeta::MessageMeta;
 use self::statefile::StateFile;
 pub use self::syncflags::SyncFlags;
-use crate::config::Config;
 use crate::imapw::UidResult;
 use imap::types::{Fetch, Flag, Mailbox};
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 // FIXME: Move this to imapw?
 /// Convert imap flags to maildir flags
@@ -20,27 +19,16 @@ pub fn maildir_flags_from_imap(inflags: &[Flag]) -> String {
     syncflags.to_string()
 }
 
-/// Path to the cache directory for given account and mailbox
-fn path(account: &str, mailbox: &str) -> PathBuf {
-    let mut cachefile = Config::dir();
-    cachefile.push("cache");
-    cachefile.push(account);
-    cachefile.push(mailbox);
-    // Create the cache path if it doesn't exist
-    std::fs::create_dir_all(&cachefile).ok();
-    cachefile
-}
-
-/// Path to the db file for this cache
-fn db_path(account: &str, mailbox: &str) -> PathBuf {
-    let mut dbfile = self::path(account, mailbox);
+/// Path to the db file in the cache directory
+fn db_path(dir: &Path) -> PathBuf {
+    let mut dbfile = dir.to_path_buf();
     dbfile.push("db.sqlite");
     dbfile
 }
 
-/// Path to .state file for given account and mailbox
-fn statefile(account: &str, mailbox: &str) -> PathBuf {
-    let mut cachefile = self::path(account, mailbox);
+/// Path to .state file in the cache directory
+fn statefile(dir: &Path) -> PathBuf {
+    let mut cachefile = dir.to_path_buf();
     cachefile.push("state");
     cachefile
 }
@@ -51,9 +39,20 @@ pub struct Cache {
 }
 
 impl Cache {
-    pub fn new(account: &str, mailbox: &str) -> Result<Cache, String> {
-        let db = Db::from_file(&self::db_path(account, mailbox))?;
-        let state = StateFile::new(&self::statefile(account, mailbox))?;
+    /// Open the cache in the given directory, creating it if needed.
+    pub fn new(dir: &Path) -> Result<Cache, String> {
+        std::fs::create_dir_all(dir)
+            .map_err(|e| format!("Could not create cache directory {:?}: {}", dir, e))?;
+        let db = Db::from_file(&self::db_path(dir))?;
+        let state = StateFile::new(&self::statefile(dir))?;
+        Ok(Cache { db, state })
+    }
+
+    /// Open the cache in the given directory without changing anything.
+    /// A cache that does not exist yet is empty.
+    pub fn read_only(dir: &Path) -> Result<Cache, String> {
+        let db = Db::read_only(&self::db_path(dir));
+        let state = StateFile::read_only(&self::statefile(dir))?;
         Ok(Cache { db, state })
     }
 
@@ -61,13 +60,24 @@ impl Cache {
         self.state.uid_validity() == mailbox.uid_validity.expect("No UIDVALIDITY in Mailbox")
     }
 
-    pub fn update_imap_state(&mut self, mailbox: &Mailbox) -> Result<(), String> {
+    /// Save the state of the mailbox on the server, along with the
+    /// messages that were skipped for being larger than `max_size`.
+    pub fn update_imap_state(
+        &mut self,
+        mailbox: &Mailbox,
+        skipped: &HashSet<u32>,
+        max_size: Option<u32>,
+    ) -> Result<(), String> {
+        let mut skipped_uids: Vec<u32> = skipped.iter().cloned().collect();
+        skipped_uids.sort_unstable();
         self.state.update_imap(
             mailbox.uid_validity.expect("No UIDVALIDITY in Mailbox"),
             mailbox.uid_next.expect("No UIDNEXT in Mailbox"),
             mailbox
                 .highest_mod_seq
                 .expect("No HIGHESTMODSEQ in Mailbox"),
+            skipped_uids,
+            max_size,
         )
     }
 
@@ -79,6 +89,12 @@ impl Cache {
         self.state.highest_mod_seq()
     }
 
+    /// The messages that were skipped for being too large, and the
+    /// max_size they were skipped with.
+    pub fn get_skipped_uids(&self) -> (Vec<u32>, Option<u32>) {
+        (self.state.skipped_uids().to_vec(), self.state.max_size())
+    }
+
     /*
     pub fn set_highest_mod_seq(&mut self, seq: u64) -> Result<(), String> {
         if seq > self.state.highest_mod_seq() {
@@ -4,6 +4,8 @@ use std::path::{Path, PathBuf};
 pub struct StateFile {
     path: PathBuf,
     state: StateFileFields,
+    /// Changes are not saved.
+    read_only: bool,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -15,19 +17,43 @@ pub struct StateFileFields {
     uid_next: u32,
     last_seen_uid: u32,
     highest_mod_seq: u64,
+    /// Messages that were too large to download, which a quick sync
+    /// does not see again unless they change.
+    #[serde(default)]
+    skipped_uids: Vec<u32>,
+    /// The max_size the messages were skipped with.
+    #[serde(default)]
+    max_size: Option<u32>,
 }
 
 impl StateFile {
     pub fn new(path: &Path) -> Result<StateFile, String> {
         if path.exists() {
-            StateFile::from_file(&path)
+            StateFile::from_file(path)
         } else {
-            StateFile::make_new(&path)
+            StateFile::make_new(path)
         }
     }
 
+    /// Read the state file at the given path, or start with a blank
+    /// state if it does not exist, without ever saving it.
+    pub fn read_only(path: &Path) -> Result<StateFile, String> {
+        let mut state = if path.exists() {
+            StateFile::from_file(path)?
+        } else {
+            StateFile::blank(path)
+        };
+        state.read_only = true;
+        Ok(state)
+    }
+
     fn make_new(path: &Path) -> Result<StateFile, String> {
-        let blank = StateFile {
+        let blank = StateFile::blank(path);
+        blank.save().map(|_| blank)
+    }
+
+    fn blank(path: &Path) -> StateFile {
+        StateFile {
             path: path.to_path_buf(),
             state: StateFileFields {
                 version: 1,
@@ -37,9 +63,11 @@ impl StateFile {
                 uid_next: 0,
                 last_seen_uid: 0,
                 highest_mod_seq: 0,
+                skipped_uids: Vec::new(),
+                max_size: None,
             },
-        };
-        blank.save().map(|_| blank)
+            read_only: false,
+        }
     }
 
     fn from_file(path: &Path) -> Result<StateFile, String> {
@@ -49,6 +77,7 @@ impl StateFile {
             .map(|state| StateFile {
                 path: path.to_path_buf(),
                 state,
+                read_only: false,
             })
     }
 
@@ -57,11 +86,15 @@ impl StateFile {
         uid_validity: u32,
         uid_next: u32,
         highest_mod_seq: u64,
+        skipped_uids: Vec<u32>,
+        max_size: Option<u32>,
     ) -> Result<(), String> {
         self.state.imap_last = chrono::offset::Utc::now().timestamp_millis();
         self.state.uid_validity = uid_validity;
         self.state.uid_next = uid_next;
         self.state.highest_mod_seq = highest_mod_seq;
+        self.state.skipped_uids = skipped_uids;
+        self.state.max_size = max_size;
         self.save()
     }
 
@@ -83,10 +116,13 @@ impl StateFile {
     */
 
     pub fn save(&self) -> Result<(), String> {
+        if self.read_only {
+            return Err(format!("{} is read-only", self.path.display()));
+        }
         std::fs::File::create(&self.path)
             .and_then(|mut f| {
                 f.write_all(
-                    &serde_json::to_string_pretty(&self.state)
+                    serde_json::to_string_pretty(&self.state)
                         .unwrap()
                         .as_bytes(),
                 )
@@ -117,4 +153,12 @@ impl StateFile {
     pub fn highest_mod_seq(&self) -> u64 {
         self.state.highest_mod_seq
     }
+
+    pub fn skipped_uids(&self) -> &[u32] {
+        &self.state.skipped_uids
+    }
+
+    pub fn max_size(&self) -> Option<u32> {
+        self.state.max_size
+    }
 }
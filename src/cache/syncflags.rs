@@ -94,8 +94,8 @@ impl From<&[Flag<'_>]> for SyncFlags {
     }
 }
 
-impl ToString for SyncFlags {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for SyncFlags {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let mut s = String::with_capacity(5);
         for i in 0..self.maildir.len() {
             match self.maildir[i] {
@@ -107,7 +107,7 @@ impl ToString for SyncFlags {
                 _ => (),
             }
         }
-        s
+        f.write_str(&s)
     }
 }
 
@@ -143,7 +143,7 @@ impl SyncFlags {
         true
     }
 
-    pub fn as_imap_flags(&self) -> Option<Vec<Flag>> {
+    pub fn as_imap_flags(&self) -> Option<Vec<Flag<'_>>> {
         let mut res = Vec::<Flag>::with_capacity(self.maildir.len());
         for flag in &self.maildir {
             match *flag {
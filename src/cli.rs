@@ -0,0 +1,286 @@
+use log::LevelFilter;
+use std::path::PathBuf;
+use std::vec::Vec;
+
+/// What the program has been asked to do.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Sync,
+    CheckConfig,
+    Ctl,
+    Help,
+    Version,
+}
+
+/// Options parsed from the command line.
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub command: Command,
+    pub config: Option<PathBuf>,
+    pub accounts: Vec<String>,
+    pub mailboxes: Vec<String>,
+    pub once: bool,
+    pub dry_run: bool,
+    pub confirm_deletions: bool,
+    pub socket: Option<PathBuf>,
+    /// How much more detail to log than the default. Negative to log less.
+    pub verbosity: i32,
+    /// Where to log: "stderr", "syslog", or a file.
+    pub log: Option<String>,
+    /// Trace the IMAP conversation of every mailbox.
+    pub trace_imap: bool,
+    /// Where to write the JSON activity stream: "-" for stdout, or a file.
+    pub events: Option<String>,
+    /// Where to export metrics: an address to serve them over HTTP, or
+    /// a file.
+    pub metrics: Option<String>,
+    /// The request for `ctl`, split into words.
+    pub request: Vec<String>,
+}
+
+impl Options {
+    /// Parse options from the given arguments, not including the program name.
+    pub fn parse<I>(args: I) -> Result<Options, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut opts = Options {
+            command: Command::Sync,
+            config: None,
+            accounts: Vec::new(),
+            mailboxes: Vec::new(),
+            once: false,
+            dry_run: false,
+            confirm_deletions: false,
+            socket: None,
+            verbosity: 0,
+            log: None,
+            trace_imap: false,
+            events: None,
+            metrics: None,
+            request: Vec::new(),
+        };
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            // Allow both "--opt value" and "--opt=value"
+            let (name, mut value) = match arg.find('=') {
+                Some(n) if arg.starts_with("--") => {
+                    (arg[..n].to_string(), Some(arg[n + 1..].to_string()))
+                }
+                _ => (arg.clone(), None),
+            };
+            let mut value_for = |opt: &str| {
+                value
+                    .take()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("Option {} requires a value", opt))
+            };
+
+            // Everything that is not an option after ctl is the request
+            if opts.command == Command::Ctl && !name.starts_with('-') {
+                opts.request.push(arg);
+                continue;
+            }
+
+            match name.as_str() {
+                "-h" | "--help" => opts.command = Command::Help,
+                "-V" | "--version" => opts.command = Command::Version,
+                "-c" | "--config" => opts.config = Some(PathBuf::from(value_for(&name)?)),
+                "-a" | "--account" => opts.accounts.push(value_for(&name)?),
+                "-m" | "--mailbox" => opts.mailboxes.push(value_for(&name)?),
+                "-1" | "--once" => opts.once = true,
+                "-n" | "--dry-run" => opts.dry_run = true,
+                "--confirm-deletions" => opts.confirm_deletions = true,
+                "-s" | "--socket" => opts.socket = Some(PathBuf::from(value_for(&name)?)),
+                "-v" | "--verbose" => opts.verbosity += 1,
+                "-vv" => opts.verbosity += 2,
+                "-q" | "--quiet" => opts.verbosity -= 1,
+                "-qq" => opts.verbosity -= 2,
+                "--log" => opts.log = Some(value_for(&name)?),
+                "--trace-imap" => opts.trace_imap = true,
+                "--events" => opts.events = Some(value_for(&name)?),
+                "--metrics" => opts.metrics = Some(value_for(&name)?),
+                "check-config" if opts.command == Command::Sync => {
+                    opts.command = Command::CheckConfig
+                }
+                "ctl" if opts.command == Command::Sync => opts.command = Command::Ctl,
+                _ => return Err(format!("Unknown argument: {}", arg)),
+            }
+
+            if value.is_some() {
+                return Err(format!("Option {} does not take a value", name));
+            }
+        }
+
+        if opts.command == Command::Ctl && opts.request.is_empty() {
+            return Err("ctl requires a request".to_string());
+        }
+
+        // A dry run is a single pass that changes nothing.
+        if opts.dry_run {
+            opts.once = true;
+        }
+        Ok(opts)
+    }
+
+    /// The usage message printed for --help.
+    pub fn usage() -> String {
+        format!(
+            "Usage: {0} [OPTIONS]
+       {0} check-config [-c <FILE>]
+       {0} ctl [-c <FILE>] [-s <SOCKET>] <REQUEST>
+
+Synchronize IMAP and Maildir.
+
+Commands:
+  check-config             Check the config file for errors without connecting
+  ctl <REQUEST>            Send a request to the running runt. One of:
+                             sync [<ACCOUNT> [<MAILBOX>]]
+                             status
+                             reload
+                             pause [<ACCOUNT>]
+                             resume [<ACCOUNT>]
+
+Options:
+  -c, --config <FILE>      Read the config from FILE instead of ~/.config/runt/config
+  -a, --account <NAME>     Only synchronize account NAME (may be repeated)
+  -m, --mailbox <NAME>     Only synchronize mailbox NAME (may be repeated)
+  -1, --once               Synchronize each mailbox once and exit
+  -n, --dry-run            Report what would be synchronized without changing anything
+      --confirm-deletions  Allow deletions that exceed the account deletion limits
+  -s, --socket <SOCKET>    Use SOCKET for the control socket instead of runt.sock
+                           in the state directory
+  -v, --verbose            Log more detail: -v for debug messages, -vv for trace
+  -q, --quiet              Log less: -q for only warnings and errors, -qq for errors
+      --log <OUTPUT>       Log to OUTPUT: stderr (the default), syslog, or a file,
+                           which is rotated when it reaches 10 MB
+      --trace-imap         Write the IMAP conversation of each mailbox to a trace
+                           file in its cache directory, without credentials or
+                           message contents
+      --events <OUTPUT>    Write a JSON line for everything synchronization does
+                           to OUTPUT: - for standard output, a named pipe, or a file
+      --metrics <TARGET>   Export Prometheus metrics on TARGET: an address such as
+                           127.0.0.1:9123 to serve them over HTTP, or a file for
+                           the node exporter textfile collector
+  -h, --help               Print this help and exit
+  -V, --version            Print the version and exit",
+            env!("CARGO_PKG_NAME")
+        )
+    }
+
+    /// The most detailed level of log messages to show.
+    pub fn log_level(&self) -> LevelFilter {
+        match self.verbosity {
+            i32::MIN..=-2 => LevelFilter::Error,
+            -1 => LevelFilter::Warn,
+            0 => LevelFilter::Info,
+            1 => LevelFilter::Debug,
+            _ => LevelFilter::Trace,
+        }
+    }
+
+    /// Was this account selected on the command line?
+    /// If no accounts were given, then all accounts are selected.
+    pub fn is_account_selected(&self, account: &str) -> bool {
+        self.accounts.is_empty() || self.accounts.iter().any(|a| a == account)
+    }
+
+    /// Was this mailbox selected on the command line?
+    /// If no mailboxes were given, then all mailboxes are selected.
+    pub fn is_mailbox_selected(&self, mailbox: &str) -> bool {
+        self.mailboxes.is_empty() || self.mailboxes.iter().any(|m| m == mailbox)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults() {
+        let opts = parse(&[]).unwrap();
+        assert_eq!(opts.command, Command::Sync);
+        assert_eq!(opts.config, None);
+        assert!(opts.accounts.is_empty());
+        assert!(!opts.once);
+        assert_eq!(opts.log_level(), LevelFilter::Info);
+        assert!(opts.is_account_selected("home"));
+        assert!(opts.is_mailbox_selected("INBOX"));
+    }
+
+    #[test]
+    fn values_separate_or_after_equals() {
+        let opts = parse(&["-c", "a.toml", "--account=home", "--account", "work"]).unwrap();
+        assert_eq!(opts.config, Some(PathBuf::from("a.toml")));
+        assert_eq!(opts.accounts, ["home", "work"]);
+        assert!(opts.is_account_selected("work"));
+        assert!(!opts.is_account_selected("other"));
+
+        let opts = parse(&["--mailbox=Lists/a=b"]).unwrap();
+        assert_eq!(opts.mailboxes, ["Lists/a=b"]);
+    }
+
+    #[test]
+    fn bad_values() {
+        assert_eq!(
+            parse(&["--config"]).unwrap_err(),
+            "Option --config requires a value"
+        );
+        assert_eq!(
+            parse(&["--once=yes"]).unwrap_err(),
+            "Option --once does not take a value"
+        );
+        assert_eq!(
+            parse(&["--frobnicate"]).unwrap_err(),
+            "Unknown argument: --frobnicate"
+        );
+        assert_eq!(parse(&["sync"]).unwrap_err(), "Unknown argument: sync");
+    }
+
+    #[test]
+    fn dry_run_implies_once() {
+        let opts = parse(&["-n"]).unwrap();
+        assert!(opts.dry_run);
+        assert!(opts.once);
+    }
+
+    #[test]
+    fn verbosity() {
+        assert_eq!(parse(&["-v"]).unwrap().log_level(), LevelFilter::Debug);
+        assert_eq!(
+            parse(&["-v", "-v"]).unwrap().log_level(),
+            LevelFilter::Trace
+        );
+        assert_eq!(parse(&["-q"]).unwrap().log_level(), LevelFilter::Warn);
+        assert_eq!(
+            parse(&["-qq", "-q"]).unwrap().log_level(),
+            LevelFilter::Error
+        );
+    }
+
+    #[test]
+    fn commands() {
+        let opts = parse(&["check-config", "-c", "a.toml"]).unwrap();
+        assert_eq!(opts.command, Command::CheckConfig);
+        assert_eq!(parse(&["-h"]).unwrap().command, Command::Help);
+        assert_eq!(
+            parse(&["check-config", "ctl"]).unwrap_err(),
+            "Unknown argument: ctl"
+        );
+    }
+
+    #[test]
+    fn ctl_request() {
+        let opts = parse(&["ctl", "-s", "/run/runt.sock", "sync", "home", "INBOX"]).unwrap();
+        assert_eq!(opts.command, Command::Ctl);
+        assert_eq!(opts.socket, Some(PathBuf::from("/run/runt.sock")));
+        assert_eq!(opts.request, ["sync", "home", "INBOX"]);
+
+        assert_eq!(parse(&["ctl"]).unwrap_err(), "ctl requires a request");
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// What is wrong with the config.
+#[derive(Debug)]
+pub enum ErrorKind {
+    /// The file could not be read.
+    Read(io::Error),
+    /// The file is not valid TOML, has an unknown key, or a value
+    /// of the wrong type.
+    Parse(String),
+    /// An `include` could not be resolved.
+    Include(String),
+    /// A `${VAR}` or `~` in a setting could not be expanded.
+    Expand(String),
+    /// The account has no password, password_command or oauth_token_command.
+    MissingCredentials { account: String },
+    /// More than one account has this name.
+    DuplicateAccount { account: String },
+    /// The Maildirs of two accounts overlap, so they would
+    /// synchronize into each other's mailboxes.
+    OverlappingMaildirs { account: String, other: String },
+    /// The cache directories of two accounts overlap, so they would
+    /// read each other's messages and state.
+    OverlappingCacheDirs { account: String, other: String },
+    /// Some other setting of the account is invalid.
+    Invalid { account: String, message: String },
+}
+
+/// An error in a config file, with the line it is on if we know it.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub line: Option<usize>,
+    pub kind: ErrorKind,
+}
+
+impl ConfigError {
+    pub fn new(path: &Path, line: Option<usize>, kind: ErrorKind) -> ConfigError {
+        ConfigError {
+            path: path.to_path_buf(),
+            line,
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ErrorKind::Read(e) => write!(f, "Could not read config: {}", e),
+            ErrorKind::Parse(message) => f.write_str(message),
+            ErrorKind::Include(message) => write!(f, "Could not include: {}", message),
+            ErrorKind::Expand(message) => f.write_str(message),
+            ErrorKind::MissingCredentials { account } => write!(
+                f,
+                "Account {:?} has no password, password_command or oauth_token_command",
+                account
+            ),
+            ErrorKind::DuplicateAccount { account } => {
+                write!(f, "There is more than one account named {:?}", account)
+            }
+            ErrorKind::OverlappingMaildirs { account, other } => write!(
+                f,
+                "The Maildir of account {:?} overlaps with the Maildir of account {:?}",
+                account, other
+            ),
+            ErrorKind::OverlappingCacheDirs { account, other } => write!(
+                f,
+                "The cache_dir of account {:?} overlaps with the cache_dir of account {:?}",
+                account, other
+            ),
+            ErrorKind::Invalid { account, message } => {
+                write!(f, "Account {:?}: {}", account, message)
+            }
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.path.display(), line, self.kind),
+            None => write!(f, "{}: {}", self.path.display(), self.kind),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+/// Expand a leading `~` to the home directory, and every `${VAR}` to
+/// the value of the environment variable VAR.
+pub fn expand(value: &str) -> Result<String, String> {
+    let mut expanded = String::with_capacity(value.len());
+    let mut rest = value;
+    if rest == "~" || rest.starts_with("~/") {
+        let home = dirs_next::home_dir().ok_or("There is no home directory to expand ~ to")?;
+        expanded.push_str(&home.to_string_lossy());
+        rest = &rest[1..];
+    }
+    while let Some(start) = rest.find("${") {
+        expanded.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| format!("Missing }} after ${{ in {:?}", value))?;
+        let name = &rest[start + 2..start + end];
+        let var = std::env::var(name)
+            .map_err(|_| format!("Environment variable {} in {:?} is not set", name, value))?;
+        expanded.push_str(&var);
+        rest = &rest[start + end + 1..];
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn home() -> String {
+        dirs_next::home_dir().unwrap().to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn plain_values_are_unchanged() {
+        assert_eq!(expand("imap.example.com").unwrap(), "imap.example.com");
+        assert_eq!(expand("").unwrap(), "");
+        assert_eq!(expand("$HOME/{mail}").unwrap(), "$HOME/{mail}");
+    }
+
+    #[test]
+    fn leading_tilde() {
+        assert_eq!(expand("~").unwrap(), home());
+        assert_eq!(expand("~/Mail").unwrap(), format!("{}/Mail", home()));
+        // Only a leading ~ on its own is the home directory
+        assert_eq!(expand("~user/Mail").unwrap(), "~user/Mail");
+        assert_eq!(expand("/mail/~/x").unwrap(), "/mail/~/x");
+    }
+
+    #[test]
+    fn variables() {
+        std::env::set_var("RUNT_TEST_EXPAND_USER", "alice");
+        std::env::set_var("RUNT_TEST_EXPAND_HOST", "example.com");
+        assert_eq!(
+            expand("${RUNT_TEST_EXPAND_USER}@${RUNT_TEST_EXPAND_HOST}").unwrap(),
+            "alice@example.com"
+        );
+        assert_eq!(
+            expand("~/Mail/${RUNT_TEST_EXPAND_USER}").unwrap(),
+            format!("{}/Mail/alice", home())
+        );
+    }
+
+    #[test]
+    fn unset_variable() {
+        std::env::remove_var("RUNT_TEST_EXPAND_UNSET");
+        assert_eq!(
+            expand("/mail/${RUNT_TEST_EXPAND_UNSET}").unwrap_err(),
+            "Environment variable RUNT_TEST_EXPAND_UNSET in \"/mail/${RUNT_TEST_EXPAND_UNSET}\" is not set"
+        );
+    }
+
+    #[test]
+    fn unclosed_variable() {
+        assert_eq!(
+            expand("/mail/${USER").unwrap_err(),
+            "Missing } after ${ in \"/mail/${USER\""
+        );
+    }
+}
@@ -0,0 +1,908 @@
+mod error;
+mod expand;
+mod pattern;
+
+pub use self::error::{ConfigError, ErrorKind};
+use self::expand::expand;
+pub use self::pattern::Pattern;
+use crate::imapw::parse_fingerprint;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+/// How long the output of a credential command is used for, by default.
+const DEFAULT_CREDENTIAL_TTL: u64 = 300;
+
+/// Which way changes are synchronized between the server and the Maildir.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Server changes are copied to the Maildir, local changes are never sent.
+    Pull,
+    /// Local changes are sent to the server, server changes are never copied.
+    Push,
+    /// Changes are synchronized both ways.
+    Both,
+}
+
+impl Direction {
+    /// Do we copy changes from the server to the Maildir?
+    pub fn pulls(self) -> bool {
+        self != Direction::Push
+    }
+
+    /// Do we send changes from the Maildir to the server?
+    pub fn pushes(self) -> bool {
+        self != Direction::Pull
+    }
+}
+
+/// How the connection to the server is secured.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum Tls {
+    /// TLS from the start of the connection, usually on port 993.
+    #[serde(rename = "implicit")]
+    Implicit,
+    /// A plaintext connection upgraded with STARTTLS, usually on port 143.
+    #[serde(rename = "starttls")]
+    Starttls,
+    /// No TLS at all. Only allowed to loopback addresses unless forced.
+    #[serde(rename = "none")]
+    Plaintext,
+}
+
+/// A way of authenticating with the server.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum AuthMechanism {
+    /// The IMAP LOGIN command with `password`.
+    #[serde(rename = "login")]
+    Login,
+    /// SASL PLAIN with `password`.
+    #[serde(rename = "plain")]
+    Plain,
+    /// SASL XOAUTH2 with a token from `oauth_token_command`.
+    #[serde(rename = "xoauth2")]
+    XOAuth2,
+    /// SASL OAUTHBEARER with a token from `oauth_token_command`.
+    #[serde(rename = "oauthbearer")]
+    OAuthBearer,
+}
+
+impl AuthMechanism {
+    /// The name used for this mechanism in the config file.
+    pub fn name(self) -> &'static str {
+        match self {
+            AuthMechanism::Login => "login",
+            AuthMechanism::Plain => "plain",
+            AuthMechanism::XOAuth2 => "xoauth2",
+            AuthMechanism::OAuthBearer => "oauthbearer",
+        }
+    }
+}
+
+/// Settings for a single mailbox that override the account settings.
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MailboxConfig {
+    pub name: Pattern,
+    pub exclude: Option<bool>,
+    pub idle: Option<bool>,
+    pub direction: Option<Direction>,
+    pub path: Option<String>,
+    pub max_size: Option<u32>,
+    pub sync_flags: Option<bool>,
+    pub trace_imap: Option<bool>,
+}
+
+/// The settings for a single mailbox, after applying any mailbox
+/// overrides to the account settings.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MailboxSettings {
+    /// The mailbox is not synchronized at all.
+    pub excluded: bool,
+    /// The mailbox is IDLEd and monitored for changes.
+    pub idle: bool,
+    /// Which way changes are synchronized.
+    pub direction: Direction,
+    /// Where the Maildir for this mailbox is.
+    pub maildir: PathBuf,
+    /// Messages larger than this are not downloaded.
+    pub max_size: Option<u32>,
+    /// Flag changes are synchronized.
+    pub sync_flags: bool,
+    /// The IMAP conversation is written to a trace file.
+    pub trace_imap: bool,
+}
+
+/// Output of credential commands and when it was fetched, keyed by
+/// command. Shared by all the clones of an account.
+#[derive(Clone, Default)]
+struct CredentialCache(Arc<Mutex<HashMap<String, (String, Instant)>>>);
+
+/// The cache is not a setting, so it never makes accounts differ.
+impl PartialEq for CredentialCache {
+    fn eq(&self, _other: &CredentialCache) -> bool {
+        true
+    }
+}
+
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Account {
+    pub account: String,
+    pub server: String,
+    pub port: Option<u16>,
+    pub tls: Option<Tls>,
+    pub allow_plaintext: Option<bool>,
+    pub ca_file: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_key: Option<String>,
+    pub fingerprint: Option<String>,
+    pub username: String,
+    pub maildir: String,
+    pub password_command: Option<String>,
+    pub password: Option<String>,
+    pub oauth_token_command: Option<String>,
+    pub auth_mechanisms: Option<Vec<AuthMechanism>>,
+    pub credential_ttl: Option<u64>,
+    pub include: Option<Vec<Pattern>>,
+    pub exclude: Option<Vec<Pattern>>,
+    pub idle: Option<Vec<Pattern>>,
+    pub cache_dir: Option<String>,
+    pub max_concurrency: Option<usize>,
+    pub max_deletions: Option<usize>,
+    pub max_deletions_percent: Option<usize>,
+    pub direction: Option<Direction>,
+    pub max_size: Option<u32>,
+    pub sync_flags: Option<bool>,
+    pub trace_imap: Option<bool>,
+    pub new_mail_command: Option<String>,
+    pub mailboxes: Option<Vec<MailboxConfig>>,
+    #[serde(skip)]
+    credentials: CredentialCache,
+}
+
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub state_dir: Option<String>,
+    pub include: Option<Vec<String>>,
+    #[serde(default)]
+    pub accounts: Vec<Account>,
+}
+
+impl Config {
+    /// Read the config from the given file, and any files it includes,
+    /// and check that it is valid.
+    pub fn new(path: &Path) -> Result<Config, Vec<ConfigError>> {
+        let mut origins = Vec::new();
+        let mut configs = Config::read(path, &mut origins, &mut Vec::new())?;
+
+        let mut errors = Vec::new();
+        if let Err(e) = expand_option(&mut configs.state_dir) {
+            errors.push(ConfigError::new(path, None, ErrorKind::Expand(e)));
+        }
+        for (config, (path, line)) in configs.accounts.iter_mut().zip(&origins) {
+            if let Err(e) = config.expand() {
+                errors.push(ConfigError::new(path, *line, ErrorKind::Expand(e)));
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let state_dir = configs.state_dir();
+        for config in &mut configs.accounts {
+            if config.cache_dir.is_none() {
+                let mut cache_dir = state_dir.join("cache");
+                cache_dir.push(&config.account);
+                config.cache_dir = Some(cache_dir.to_string_lossy().to_string());
+            }
+            if config.port.is_none() {
+                config.port = match config.tls {
+                    Some(Tls::Starttls) | Some(Tls::Plaintext) => Some(143),
+                    _ => Some(993),
+                };
+            }
+        }
+
+        let errors = configs.validate(&origins);
+        if errors.is_empty() {
+            Ok(configs)
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Find the state directory from the config at the given path, for
+    /// `runt ctl`, which needs nothing else. Only `state_dir` is read,
+    /// so accounts that are not valid, or an include that is missing,
+    /// don't get in the way.
+    pub fn read_state_dir(path: &Path) -> Result<PathBuf, Vec<ConfigError>> {
+        #[derive(Deserialize)]
+        struct StateDir {
+            state_dir: Option<String>,
+        }
+
+        let mut state_dir = parse::<StateDir>(path, &read_file(path)?)?.state_dir;
+        expand_option(&mut state_dir)
+            .map_err(|e| vec![ConfigError::new(path, None, ErrorKind::Expand(e))])?;
+        let config = Config {
+            state_dir,
+            include: None,
+            accounts: Vec::new(),
+        };
+        Ok(config.state_dir())
+    }
+
+    /// Parse a single config file, and then the files it includes.
+    /// The file and line of each account are added to `origins`, and
+    /// `seen` holds the files we are already reading, to catch loops.
+    fn read(
+        path: &Path,
+        origins: &mut Vec<(PathBuf, Option<usize>)>,
+        seen: &mut Vec<PathBuf>,
+    ) -> Result<Config, Vec<ConfigError>> {
+        let error = |line, kind| vec![ConfigError::new(path, line, kind)];
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if seen.contains(&canonical) {
+            let message = format!("{} is included in a loop", path.display());
+            return Err(error(None, ErrorKind::Include(message)));
+        }
+        let buf = read_file(path)?;
+        let mut configs: Config = parse(path, &buf)?;
+        if !seen.is_empty() && configs.state_dir.is_some() {
+            let message = "state_dir can only be set in the main config file";
+            return Err(error(None, ErrorKind::Include(message.to_string())));
+        }
+
+        let lines = account_lines(&buf);
+        for i in 0..configs.accounts.len() {
+            origins.push((path.to_path_buf(), lines.get(i).cloned()));
+        }
+
+        seen.push(canonical);
+        for include in configs.include.take().unwrap_or_default() {
+            let files = include_files(path, &include).map_err(|e| error(None, e))?;
+            for file in files {
+                let mut included = Config::read(&file, origins, seen)?;
+                configs.accounts.append(&mut included.accounts);
+            }
+        }
+        seen.pop();
+        Ok(configs)
+    }
+
+    /// Check the settings that TOML parsing can't, and return everything
+    /// that is wrong. `origins` has the file and line of each account.
+    fn validate(&self, origins: &[(PathBuf, Option<usize>)]) -> Vec<ConfigError> {
+        let mut errors = Vec::new();
+        for (i, (account, (path, line))) in self.accounts.iter().zip(origins).enumerate() {
+            let mut error = |kind| errors.push(ConfigError::new(path, *line, kind));
+            let name = account.account.clone();
+
+            if self.accounts[..i].iter().any(|a| a.account == name) {
+                error(ErrorKind::DuplicateAccount {
+                    account: name.clone(),
+                });
+            }
+            if !account.has_password() && account.oauth_token_command.is_none() {
+                error(ErrorKind::MissingCredentials {
+                    account: name.clone(),
+                });
+            }
+            let root = account.root();
+            let overlapping = self.accounts[..i].iter().find(|other| {
+                let other_root = other.root();
+                other.account != name
+                    && (root.starts_with(&other_root) || other_root.starts_with(&root))
+            });
+            if let Some(other) = overlapping {
+                error(ErrorKind::OverlappingMaildirs {
+                    account: name.clone(),
+                    other: other.account.clone(),
+                });
+            }
+            let cache_dir = PathBuf::from(account.cache_dir.as_ref().unwrap());
+            let overlapping = self.accounts[..i].iter().find(|other| {
+                let other_dir = PathBuf::from(other.cache_dir.as_ref().unwrap());
+                other.account != name
+                    && (cache_dir.starts_with(&other_dir) || other_dir.starts_with(&cache_dir))
+            });
+            if let Some(other) = overlapping {
+                error(ErrorKind::OverlappingCacheDirs {
+                    account: name.clone(),
+                    other: other.account.clone(),
+                });
+            }
+            if account.client_cert.is_some() != account.client_key.is_some() {
+                error(ErrorKind::Invalid {
+                    account: name.clone(),
+                    message: "client_cert and client_key must be set together".to_string(),
+                });
+            }
+            let files = [
+                ("ca_file", &account.ca_file),
+                ("client_cert", &account.client_cert),
+                ("client_key", &account.client_key),
+            ];
+            for (setting, file) in files.iter() {
+                if let Some(file) = file {
+                    if !Path::new(file).exists() {
+                        error(ErrorKind::Invalid {
+                            account: name.clone(),
+                            message: format!("{} {} does not exist", setting, file),
+                        });
+                    }
+                }
+            }
+            if let Some(fingerprint) = &account.fingerprint {
+                if let Err(message) = parse_fingerprint(fingerprint) {
+                    error(ErrorKind::Invalid {
+                        account: name.clone(),
+                        message,
+                    });
+                }
+            }
+            if let Some(mechanisms) = &account.auth_mechanisms {
+                if mechanisms.is_empty() {
+                    error(ErrorKind::Invalid {
+                        account: name.clone(),
+                        message: "auth_mechanisms is empty".to_string(),
+                    });
+                }
+            }
+            for message in account.check_mailbox_paths() {
+                error(ErrorKind::Invalid {
+                    account: name.clone(),
+                    message,
+                });
+            }
+        }
+        errors
+    }
+
+    /// Path to the default config file. This is `$XDG_CONFIG_HOME/runt/config`,
+    /// unless only the old `~/.runt/config` exists.
+    pub fn default_path() -> PathBuf {
+        let mut path = dirs_next::config_dir().unwrap_or_else(Config::legacy_dir);
+        path.push("runt");
+        path.push("config");
+        let legacy = Config::legacy_dir().join("config");
+        if !path.exists() && legacy.exists() {
+            return legacy;
+        }
+        path
+    }
+
+    /// The directory for the caches and other state: `state_dir` if it
+    /// is set, otherwise `$XDG_STATE_HOME/runt`, unless there is already
+    /// a cache in the old `~/.runt` and not in the new place.
+    pub fn state_dir(&self) -> PathBuf {
+        if let Some(dir) = &self.state_dir {
+            return PathBuf::from(dir);
+        }
+        let state_home = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| dirs_next::home_dir().map(|home| home.join(".local/state")))
+            .unwrap_or_else(Config::legacy_dir);
+        let dir = state_home.join("runt");
+        let legacy = Config::legacy_dir();
+        if legacy.join("cache").exists() && !dir.join("cache").exists() {
+            return legacy;
+        }
+        dir
+    }
+
+    /// Where config and state were kept before we followed the XDG spec.
+    fn legacy_dir() -> PathBuf {
+        let mut home = match dirs_next::home_dir() {
+            Some(path) => path,
+            _ => PathBuf::from(""),
+        };
+        home.push(".runt");
+        home
+    }
+}
+
+/// The line numbers of the `account = ` keys in the source, which
+/// are in the same order as the accounts.
+fn account_lines(source: &str) -> Vec<usize> {
+    source
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| {
+            let mut parts = line.splitn(2, '=');
+            parts.next().map(str::trim) == Some("account") && parts.next().is_some()
+        })
+        .map(|(n, _)| n + 1)
+        .collect()
+}
+
+/// The files matched by an `include` in the config file at `path`.
+/// Relative includes are relative to the directory of that file, and
+/// only the file name may have wildcards. Matches are sorted by name.
+fn include_files(path: &Path, include: &str) -> Result<Vec<PathBuf>, ErrorKind> {
+    let include = expand(include).map_err(ErrorKind::Expand)?;
+    let include = path.parent().unwrap_or_else(|| Path::new("")).join(include);
+    let name = include
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    if !name.contains(['*', '?']) {
+        return Ok(vec![include]);
+    }
+
+    let pattern = Pattern::new(&name).map_err(ErrorKind::Include)?;
+    let dir = include.parent().unwrap_or_else(|| Path::new(""));
+    let entries = std::fs::read_dir(dir)
+        .map_err(|e| ErrorKind::Include(format!("{}: {}", dir.display(), e)))?;
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|file| {
+            file.is_file()
+                && file
+                    .file_name()
+                    .is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Read the whole of a config file.
+fn read_file(path: &Path) -> Result<String, Vec<ConfigError>> {
+    let mut buf = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut buf))
+        .map_err(|e| vec![ConfigError::new(path, None, ErrorKind::Read(e))])?;
+    Ok(buf)
+}
+
+/// Parse the TOML of a config file.
+fn parse<T: DeserializeOwned>(path: &Path, buf: &str) -> Result<T, Vec<ConfigError>> {
+    toml::from_str(buf).map_err(|e| {
+        // We report the line ourselves, so drop it from the message
+        let line = e.line_col().map(|(line, _)| line + 1);
+        let mut message = e.to_string();
+        if let Some((line, col)) = e.line_col() {
+            let location = format!(" at line {} column {}", line + 1, col + 1);
+            if message.ends_with(&location) {
+                message.truncate(message.len() - location.len());
+            }
+        }
+        vec![ConfigError::new(path, line, ErrorKind::Parse(message))]
+    })
+}
+
+/// Expand an optional setting in place.
+fn expand_option(value: &mut Option<String>) -> Result<(), String> {
+    if let Some(v) = value {
+        *v = expand(v)?;
+    }
+    Ok(())
+}
+
+/// Run the given shell command and return its trimmed output.
+/// Used for commands that fetch credentials.
+fn command_output(command: &str) -> Result<String, String> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .output()
+        .map_err(|e| format!("Could not execute {:?}: {}", command, e))?;
+    if !output.status.success() {
+        return Err(format!("{:?} failed: {}", command, output.status));
+    }
+    String::from_utf8(output.stdout)
+        .map(|s| s.trim().to_string())
+        .map_err(|_| format!("Output of {:?} is not UTF-8", command))
+}
+
+impl Account {
+    /// Expand `${VAR}` and `~` in the settings that are paths, names
+    /// or commands. The password is left alone, since it may contain `$`.
+    fn expand(&mut self) -> Result<(), String> {
+        for value in [&mut self.server, &mut self.username, &mut self.maildir] {
+            *value = expand(value)?;
+        }
+        for value in [
+            &mut self.password_command,
+            &mut self.oauth_token_command,
+            &mut self.new_mail_command,
+            &mut self.ca_file,
+            &mut self.client_cert,
+            &mut self.client_key,
+            &mut self.cache_dir,
+        ] {
+            expand_option(value)?;
+        }
+        Ok(())
+    }
+
+    /// The authentication mechanisms to consider, in order of preference.
+    /// By default we use OAuth if there is a token command, and the
+    /// password otherwise.
+    pub fn auth_mechanisms(&self) -> Vec<AuthMechanism> {
+        match &self.auth_mechanisms {
+            Some(mechanisms) => mechanisms.clone(),
+            None if self.oauth_token_command.is_some() => {
+                vec![AuthMechanism::XOAuth2, AuthMechanism::OAuthBearer]
+            }
+            None => vec![AuthMechanism::Plain, AuthMechanism::Login],
+        }
+    }
+
+    /// Is there a password, or a command to get one?
+    pub fn has_password(&self) -> bool {
+        self.password.is_some() || self.password_command.is_some()
+    }
+
+    /// Get the password, running `password_command` if we don't
+    /// have a recent enough result from it.
+    pub fn password(&self) -> Result<String, String> {
+        match (&self.password, &self.password_command) {
+            (Some(password), _) => Ok(password.clone()),
+            (None, Some(command)) => self.credential(command),
+            (None, None) => Err("No password or password_command set".to_string()),
+        }
+    }
+
+    /// Get an OAuth access token, running `oauth_token_command` if we
+    /// don't have a recent enough result from it.
+    pub fn oauth_token(&self) -> Result<String, String> {
+        match &self.oauth_token_command {
+            Some(command) => self.credential(command),
+            None => Err("No oauth_token_command set".to_string()),
+        }
+    }
+
+    /// Forget the output of the credential commands, so that they are run
+    /// again next time. Used when the server rejects our credentials.
+    /// Returns whether there was anything to forget.
+    pub fn forget_credentials(&self) -> bool {
+        let mut credentials = self.credentials.0.lock().unwrap();
+        let any = !credentials.is_empty();
+        credentials.clear();
+        any
+    }
+
+    /// Run the credential command, or use its output from the last
+    /// `credential_ttl` seconds. The lock is held while the command runs,
+    /// so that connections made at the same time only run it once.
+    fn credential(&self, command: &str) -> Result<String, String> {
+        let ttl = Duration::from_secs(self.credential_ttl.unwrap_or(DEFAULT_CREDENTIAL_TTL));
+        let mut credentials = self.credentials.0.lock().unwrap();
+        if let Some((credential, fetched)) = credentials.get(command) {
+            if fetched.elapsed() < ttl {
+                return Ok(credential.clone());
+            }
+        }
+        let credential = command_output(command)?;
+        credentials.insert(command.to_string(), (credential.clone(), Instant::now()));
+        Ok(credential)
+    }
+
+    /// The directory that the Maildirs of this account are in.
+    fn root(&self) -> PathBuf {
+        let mut root = PathBuf::from(&self.maildir);
+        root.push(&self.account);
+        root
+    }
+
+    /// Are the settings that apply to every mailbox of the account the same?
+    /// Settings that only decide which mailboxes are synchronized, and how,
+    /// are compared through `mailbox_settings` instead.
+    pub fn same_account_settings(&self, other: &Account) -> bool {
+        let common = |account: &Account| Account {
+            include: None,
+            exclude: None,
+            idle: None,
+            max_concurrency: None,
+            direction: None,
+            max_size: None,
+            sync_flags: None,
+            trace_imap: None,
+            mailboxes: None,
+            ..account.clone()
+        };
+        common(self) == common(other)
+    }
+
+    /// The file that the IMAP conversation for the given mailbox is
+    /// traced to, or for the account if there is no mailbox.
+    pub fn trace_path(&self, mailbox: Option<&str>) -> PathBuf {
+        let mut path = match mailbox {
+            Some(mailbox) => self.cache_path(mailbox),
+            None => PathBuf::from(self.cache_dir.as_ref().unwrap()),
+        };
+        path.push("imap-trace.log");
+        path
+    }
+
+    /// The directory for the cache of the given mailbox.
+    pub fn cache_path(&self, mailbox: &str) -> PathBuf {
+        // Config::new always sets the cache_dir
+        let mut path = PathBuf::from(self.cache_dir.as_ref().unwrap());
+        path.push(mailbox);
+        path
+    }
+
+    /// The settings for the given mailbox, if there are any.
+    /// If more than one table matches, the first one wins.
+    fn mailbox_config(&self, name: &str) -> Option<&MailboxConfig> {
+        self.mailboxes
+            .as_ref()
+            .and_then(|mailboxes| mailboxes.iter().find(|m| m.name.matches(name)))
+    }
+
+    /// Resolve the settings for the given mailbox. Settings in the first
+    /// matching `[[accounts.mailboxes]]` table take precedence over the
+    /// account settings.
+    pub fn mailbox_settings(&self, name: &str) -> MailboxSettings {
+        let mailbox = self.mailbox_config(name);
+        let get = |f: fn(&MailboxConfig) -> Option<bool>| mailbox.and_then(f);
+
+        // Mailbox paths are relative to the directory for the account
+        let mut maildir = self.root();
+        match mailbox.and_then(|m| m.path.as_ref()) {
+            Some(path) => maildir.push(path),
+            None => maildir.push(name),
+        }
+
+        MailboxSettings {
+            excluded: get(|m| m.exclude).unwrap_or_else(|| self.is_mailbox_excluded(name)),
+            idle: get(|m| m.idle).unwrap_or_else(|| self.is_mailbox_idled(name)),
+            direction: mailbox
+                .and_then(|m| m.direction)
+                .or(self.direction)
+                .unwrap_or(Direction::Both),
+            maildir,
+            max_size: mailbox.and_then(|m| m.max_size).or(self.max_size),
+            sync_flags: get(|m| m.sync_flags).or(self.sync_flags).unwrap_or(true),
+            trace_imap: get(|m| m.trace_imap).or(self.trace_imap).unwrap_or(false),
+        }
+    }
+
+    /// Check that each mailbox `path` is for a single mailbox, stays inside
+    /// the account directory, and is not the Maildir of another mailbox
+    /// that has a table of its own.
+    fn check_mailbox_paths(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        let mut dirs: Vec<(&str, &Path)> = Vec::new();
+        let mailboxes = self.mailboxes.as_deref().unwrap_or_default();
+        for (i, mailbox) in mailboxes.iter().enumerate() {
+            let name = mailbox.name.as_str();
+            // Only the first table for a mailbox is used
+            if mailboxes[..i].iter().any(|m| m.name.matches(name)) {
+                continue;
+            }
+            if let Some(path) = &mailbox.path {
+                if !mailbox.name.is_literal() {
+                    errors.push(format!(
+                        "path is set for {:?}, which is a pattern and not a single mailbox",
+                        name
+                    ));
+                    continue;
+                }
+                let inside = !path.is_empty()
+                    && Path::new(path)
+                        .components()
+                        .all(|c| matches!(c, Component::Normal(_)));
+                if !inside {
+                    errors.push(format!(
+                        "path {:?} of mailbox {:?} is not inside the account directory",
+                        path, name
+                    ));
+                    continue;
+                }
+            } else if !mailbox.name.is_literal() {
+                continue;
+            }
+            let dir = Path::new(mailbox.path.as_deref().unwrap_or(name));
+            match dirs.iter().find(|(_, other)| *other == dir) {
+                Some((other, _)) => errors.push(format!(
+                    "mailboxes {:?} and {:?} have the same path",
+                    other, name
+                )),
+                None => dirs.push((name, dir)),
+            }
+        }
+        errors
+    }
+
+    /// Would deleting `count` of `total` messages in a mailbox exceed
+    /// the `max_deletions` or `max_deletions_percent` limits?
+    pub fn exceeds_deletion_limit(&self, count: usize, total: usize) -> bool {
+        if count == 0 {
+            return false;
+        }
+        if let Some(max) = self.max_deletions {
+            if count > max {
+                return true;
+            }
+        }
+        if let Some(percent) = self.max_deletions_percent {
+            if count * 100 > percent * total {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Is this mailbox excluded from synchronization?
+    /// If the account has an `include` member, then only mailboxes
+    /// matching it are synchronized. Mailboxes matching `exclude`
+    /// are never synchronized, even if they are also included.
+    fn is_mailbox_excluded(&self, name: &str) -> bool {
+        if let Some(include) = &self.include {
+            if !matches_any(include, name) {
+                return true;
+            }
+        }
+        if let Some(exclude) = &self.exclude {
+            matches_any(exclude, name)
+        } else {
+            false
+        }
+    }
+
+    /// Is this mailbox one we want to IDLE on?
+    /// If the account has a `idle` member, then only mailboxes
+    /// matching it are IDLEd. Otherwise everything that is not
+    /// `exclude`d is IDLEd.
+    fn is_mailbox_idled(&self, name: &str) -> bool {
+        if let Some(idle) = &self.idle {
+            matches_any(idle, name)
+        } else {
+            true
+        }
+    }
+}
+
+/// Does any of the patterns match the given name?
+fn matches_any(patterns: &[Pattern], name: &str) -> bool {
+    patterns.iter().any(|p| p.matches(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An account with the given settings added to the required ones.
+    fn account(settings: &str) -> Account {
+        let toml = format!(
+            "account = \"home\"\nserver = \"imap.example.com\"\n\
+             username = \"user\"\nmaildir = \"/mail\"\n{}",
+            settings
+        );
+        toml::from_str(&toml).unwrap()
+    }
+
+    #[test]
+    fn everything_is_synchronized_and_idled_by_default() {
+        let settings = account("").mailbox_settings("INBOX");
+        assert!(!settings.excluded);
+        assert!(settings.idle);
+        assert_eq!(settings.direction, Direction::Both);
+        assert_eq!(settings.maildir, PathBuf::from("/mail/home/INBOX"));
+        assert_eq!(settings.max_size, None);
+        assert!(settings.sync_flags);
+        assert!(!settings.trace_imap);
+    }
+
+    #[test]
+    fn exclude_wins_over_include() {
+        let account = account(
+            r#"include = ["INBOX", "Lists/*"]
+               exclude = ["Lists/spam-*"]"#,
+        );
+        assert!(!account.mailbox_settings("INBOX").excluded);
+        assert!(!account.mailbox_settings("Lists/rust").excluded);
+        assert!(account.mailbox_settings("Lists/spam-daily").excluded);
+        assert!(account.mailbox_settings("Sent").excluded);
+    }
+
+    #[test]
+    fn idle_only_what_is_listed() {
+        let account = account(r#"idle = ["INBOX", "re:Lists/(rust|go)"]"#);
+        assert!(account.mailbox_settings("INBOX").idle);
+        assert!(account.mailbox_settings("Lists/go").idle);
+        assert!(!account.mailbox_settings("Lists/python").idle);
+        assert!(!account.mailbox_settings("Sent").idle);
+    }
+
+    #[test]
+    fn mailbox_tables_win_over_account_lists() {
+        let account = account(
+            r#"exclude = ["Archive", "Lists/*"]
+               idle = ["INBOX"]
+               direction = "pull"
+               max_size = 1000
+
+               [[mailboxes]]
+               name = "Archive"
+               exclude = false
+               idle = true
+               path = "Archive.All"
+               direction = "push"
+               max_size = 2000
+
+               [[mailboxes]]
+               name = "Lists/*"
+               sync_flags = false"#,
+        );
+        let archive = account.mailbox_settings("Archive");
+        assert!(!archive.excluded);
+        assert!(archive.idle);
+        assert_eq!(archive.direction, Direction::Push);
+        assert_eq!(archive.maildir, PathBuf::from("/mail/home/Archive.All"));
+        assert_eq!(archive.max_size, Some(2000));
+
+        // Settings the table leaves out come from the account
+        let list = account.mailbox_settings("Lists/rust");
+        assert!(list.excluded);
+        assert!(!list.idle);
+        assert!(!list.sync_flags);
+        assert_eq!(list.direction, Direction::Pull);
+        assert_eq!(list.maildir, PathBuf::from("/mail/home/Lists/rust"));
+        assert_eq!(list.max_size, Some(1000));
+    }
+
+    #[test]
+    fn first_matching_table_wins() {
+        let account = account(
+            r#"[[mailboxes]]
+               name = "Lists/rust"
+               idle = false
+
+               [[mailboxes]]
+               name = "Lists/*"
+               exclude = true"#,
+        );
+        let rust = account.mailbox_settings("Lists/rust");
+        assert!(!rust.excluded);
+        assert!(!rust.idle);
+        assert!(account.mailbox_settings("Lists/go").excluded);
+    }
+
+    #[test]
+    fn no_deletion_limit_by_default() {
+        let account = account("");
+        assert!(!account.exceeds_deletion_limit(1000, 1000));
+    }
+
+    #[test]
+    fn deletion_limit_by_count() {
+        let account = account("max_deletions = 10");
+        assert!(!account.exceeds_deletion_limit(10, 1000));
+        assert!(account.exceeds_deletion_limit(11, 1000));
+        assert!(account.exceeds_deletion_limit(11, 11));
+    }
+
+    #[test]
+    fn deletion_limit_by_percent() {
+        let account = account("max_deletions_percent = 25");
+        assert!(!account.exceeds_deletion_limit(25, 100));
+        assert!(account.exceeds_deletion_limit(26, 100));
+        assert!(account.exceeds_deletion_limit(1, 3));
+        // Nothing to delete never exceeds the limit, even in an empty mailbox
+        assert!(!account.exceeds_deletion_limit(0, 0));
+    }
+
+    #[test]
+    fn either_deletion_limit_is_enough() {
+        let account = account("max_deletions = 10\nmax_deletions_percent = 50");
+        assert!(account.exceeds_deletion_limit(11, 1000));
+        assert!(account.exceeds_deletion_limit(6, 10));
+        assert!(!account.exceeds_deletion_limit(5, 10));
+    }
+}
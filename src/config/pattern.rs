@@ -0,0 +1,147 @@
+use regex::Regex;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+
+/// A pattern for matching mailbox names.
+///
+/// Patterns are globs by default, where `*` matches any run of characters
+/// (including the hierarchy delimiter) and `?` matches any single character.
+/// Patterns starting with `re:` are regular expressions. Both kinds must match
+/// the whole mailbox name.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    source: String,
+    regex: Regex,
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Pattern, String> {
+        let expr = match source.strip_prefix("re:") {
+            Some(re) => format!("^(?:{})$", re),
+            None => Pattern::glob_to_regex(source),
+        };
+        Regex::new(&expr)
+            .map(|regex| Pattern {
+                source: source.to_string(),
+                regex,
+            })
+            .map_err(|e| format!("Invalid pattern {:?}: {}", source, e))
+    }
+
+    fn glob_to_regex(glob: &str) -> String {
+        let mut expr = String::with_capacity(glob.len() + 8);
+        expr.push('^');
+        for c in glob.chars() {
+            match c {
+                '*' => expr.push_str(".*"),
+                '?' => expr.push('.'),
+                c => expr.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        expr.push('$');
+        expr
+    }
+
+    /// Is this pattern just a mailbox name, which matches only itself?
+    pub fn is_literal(&self) -> bool {
+        !self.source.starts_with("re:") && !self.source.contains(&['*', '?'][..])
+    }
+
+    /// The pattern as it was written.
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Does this pattern match the given name?
+    pub fn matches(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+}
+
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Pattern) -> bool {
+        self.source == other.source
+    }
+}
+
+struct PatternVisitor;
+
+impl<'de> Visitor<'de> for PatternVisitor {
+    type Value = Pattern;
+
+    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+        formatter.write_str(r#"a mailbox name, glob like "Lists/*", or regex like "re:^Lists/.*""#)
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Pattern, E>
+    where
+        E: de::Error,
+    {
+        Pattern::new(value).map_err(E::custom)
+    }
+}
+
+impl<'de> Deserialize<'de> for Pattern {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_str(PatternVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn globs() {
+        let pattern = Pattern::new("Lists/*").unwrap();
+        assert!(pattern.matches("Lists/rust"));
+        assert!(pattern.matches("Lists/rust/announce"));
+        assert!(pattern.matches("Lists/"));
+        assert!(!pattern.matches("Lists"));
+        assert!(!pattern.matches("Old/Lists/rust"));
+
+        let pattern = Pattern::new("Archive.20??").unwrap();
+        assert!(pattern.matches("Archive.2021"));
+        assert!(!pattern.matches("Archive.201"));
+        assert!(!pattern.matches("Archive.20210"));
+    }
+
+    #[test]
+    fn globs_escape_regex_characters() {
+        let pattern = Pattern::new("[Gmail].All Mail (old)").unwrap();
+        assert!(pattern.matches("[Gmail].All Mail (old)"));
+        assert!(!pattern.matches("G.All Mail old"));
+    }
+
+    #[test]
+    fn regexes() {
+        let pattern = Pattern::new("re:Lists/(rust|go)").unwrap();
+        assert!(pattern.matches("Lists/rust"));
+        assert!(pattern.matches("Lists/go"));
+        assert!(!pattern.matches("Lists/rust-announce"));
+        assert!(!pattern.matches("Old/Lists/go"));
+
+        // Alternatives are anchored as a whole
+        let pattern = Pattern::new("re:INBOX|Sent").unwrap();
+        assert!(pattern.matches("Sent"));
+        assert!(!pattern.matches("INBOX.old"));
+        assert!(!pattern.matches("Old Sent"));
+    }
+
+    #[test]
+    fn invalid_regex() {
+        assert!(Pattern::new("re:Lists/(rust").is_err());
+        assert!(Pattern::new("Lists/(rust").is_ok());
+    }
+
+    #[test]
+    fn literals() {
+        assert!(Pattern::new("INBOX").unwrap().is_literal());
+        assert!(Pattern::new("[Gmail].Sent").unwrap().is_literal());
+        assert!(!Pattern::new("Lists/*").unwrap().is_literal());
+        assert!(!Pattern::new("Archive.20??").unwrap().is_literal());
+        assert!(!Pattern::new("re:INBOX").unwrap().is_literal());
+    }
+}
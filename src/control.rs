@@ -0,0 +1,223 @@
+use crate::runner::Event;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Sender};
+use std::thread::spawn;
+
+/// A request sent to the control socket.
+///
+/// The protocol is a single line with the request, which gets a reply of
+/// zero or more lines of text followed by a line with `OK`, or a line
+/// with `ERR` and an error message.
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    /// `sync [<account> [<mailbox>]]`: Synchronize now.
+    Sync(Option<String>, Option<String>),
+    /// `status`: The status of every mailbox.
+    Status,
+    /// `reload`: Re-read the config.
+    Reload,
+    /// `pause [<account>]`: Stop synchronizing until resumed.
+    Pause(Option<String>),
+    /// `resume [<account>]`: Carry on after a pause.
+    Resume(Option<String>),
+}
+
+impl Request {
+    /// Parse a request line. Mailbox names may have spaces in them,
+    /// so the mailbox is the rest of the line after the account.
+    pub fn parse(line: &str) -> Result<Request, String> {
+        let line = line.trim();
+        let (command, args) = match line.find(' ') {
+            Some(n) => (&line[..n], line[n + 1..].trim()),
+            None => (line, ""),
+        };
+        let (account, mailbox) = match args.find(' ') {
+            Some(n) => (Some(&args[..n]), Some(args[n + 1..].trim())),
+            None if !args.is_empty() => (Some(args), None),
+            None => (None, None),
+        };
+        let account = account.map(str::to_string);
+        let mailbox = mailbox.map(str::to_string);
+        let no_args = |request| match account {
+            Some(_) => Err(format!("{} does not take any arguments", command)),
+            None => Ok(request),
+        };
+        let account_only = |request: fn(Option<String>) -> Request| match mailbox {
+            Some(_) => Err(format!("{} takes an account, not a mailbox", command)),
+            None => Ok(request(account.clone())),
+        };
+        match command {
+            "sync" => Ok(Request::Sync(account, mailbox)),
+            "status" => no_args(Request::Status),
+            "reload" => no_args(Request::Reload),
+            "pause" => account_only(Request::Pause),
+            "resume" => account_only(Request::Resume),
+            "" => Err("Empty request".to_string()),
+            _ => Err(format!("Unknown request: {}", command)),
+        }
+    }
+}
+
+/// A control socket that this process is listening on. The socket is
+/// removed when this is dropped.
+pub struct Listening {
+    path: PathBuf,
+}
+
+impl Drop for Listening {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+/// Listen on the control socket at the given path, and pass requests on
+/// to the runner as events. A stale socket left by a runt that is no
+/// longer running is replaced.
+pub fn listen(path: &Path, events: Sender<Event>) -> Result<Listening, String> {
+    if UnixStream::connect(path).is_ok() {
+        return Err(format!("{} is in use by another runt", path.display()));
+    }
+    fs::remove_file(path).ok();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    }
+    let listener = UnixListener::bind(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let listening = Listening {
+        path: path.to_path_buf(),
+    };
+    // Anyone who can connect can control runt
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let events = events.clone();
+            spawn(move || handle_connection(stream, events));
+        }
+    });
+    Ok(listening)
+}
+
+/// Answer the request on a control connection.
+fn handle_connection(stream: UnixStream, events: Sender<Event>) {
+    let mut line = String::new();
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    if BufReader::new(stream).read_line(&mut line).is_err() {
+        return;
+    }
+
+    let reply = Request::parse(&line).and_then(|request| {
+        let (reply_tx, reply) = channel();
+        events
+            .send(Event::Request(request, reply_tx))
+            .map_err(|_| "Shutting down".to_string())?;
+        reply.recv().map_err(|_| "Shutting down".to_string())?
+    });
+    let text = match reply {
+        Ok(text) if text.is_empty() => "OK\n".to_string(),
+        Ok(text) => format!("{}\nOK\n", text.trim_end()),
+        Err(e) => format!("ERR {}\n", e),
+    };
+    writer.write_all(text.as_bytes()).ok();
+}
+
+/// Send a request to the runt listening on the socket at `path`, and
+/// print the reply. Returns whether the request succeeded.
+pub fn send(path: &Path, request: &str) -> Result<bool, String> {
+    let mut stream = UnixStream::connect(path).map_err(|e| {
+        format!(
+            "Could not connect to {}: {}. Is runt running?",
+            path.display(),
+            e
+        )
+    })?;
+    stream
+        .write_all(format!("{}\n", request).as_bytes())
+        .map_err(|e| e.to_string())?;
+    for line in BufReader::new(stream).lines() {
+        let line = line.map_err(|e| e.to_string())?;
+        if line == "OK" {
+            return Ok(true);
+        }
+        if let Some(e) = line.strip_prefix("ERR ") {
+            eprintln!("{}", e);
+            return Ok(false);
+        }
+        println!("{}", line);
+    }
+    Err("Connection closed without a reply".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn some(s: &str) -> Option<String> {
+        Some(s.to_string())
+    }
+
+    #[test]
+    fn sync_requests() {
+        assert_eq!(Request::parse("sync\n"), Ok(Request::Sync(None, None)));
+        assert_eq!(
+            Request::parse("sync home"),
+            Ok(Request::Sync(some("home"), None))
+        );
+        assert_eq!(
+            Request::parse("sync home INBOX"),
+            Ok(Request::Sync(some("home"), some("INBOX")))
+        );
+    }
+
+    #[test]
+    fn mailboxes_may_have_spaces() {
+        assert_eq!(
+            Request::parse("sync home [Gmail]/All Mail\n"),
+            Ok(Request::Sync(some("home"), some("[Gmail]/All Mail")))
+        );
+    }
+
+    #[test]
+    fn requests_without_arguments() {
+        assert_eq!(Request::parse("status"), Ok(Request::Status));
+        assert_eq!(Request::parse("  reload  \n"), Ok(Request::Reload));
+        assert_eq!(
+            Request::parse("status home"),
+            Err("status does not take any arguments".to_string())
+        );
+    }
+
+    #[test]
+    fn requests_for_an_account() {
+        assert_eq!(Request::parse("pause"), Ok(Request::Pause(None)));
+        assert_eq!(
+            Request::parse("resume home"),
+            Ok(Request::Resume(some("home")))
+        );
+        assert_eq!(
+            Request::parse("pause home INBOX"),
+            Err("pause takes an account, not a mailbox".to_string())
+        );
+    }
+
+    #[test]
+    fn bad_requests() {
+        assert_eq!(Request::parse("\n"), Err("Empty request".to_string()));
+        assert_eq!(
+            Request::parse("stop home"),
+            Err("Unknown request: stop".to_string())
+        );
+        // Requests are case sensitive
+        assert_eq!(
+            Request::parse("SYNC"),
+            Err("Unknown request: SYNC".to_string())
+        );
+    }
+}
@@ -0,0 +1,192 @@
+use super::connection::Connection;
+use crate::config::{Account, AuthMechanism};
+use imap::error::Error;
+use imap::{Authenticator, Client, Session};
+
+/// SASL PLAIN, from RFC 4616.
+struct Plain {
+    user: String,
+    password: String,
+}
+
+impl Authenticator for Plain {
+    type Response = String;
+
+    fn process(&self, _challenge: &[u8]) -> String {
+        format!("\0{}\0{}", self.user, self.password)
+    }
+}
+
+/// SASL XOAUTH2, as used by Gmail and Outlook.
+struct XOAuth2 {
+    user: String,
+    token: String,
+}
+
+impl Authenticator for XOAuth2 {
+    type Response = String;
+
+    fn process(&self, challenge: &[u8]) -> String {
+        // A non-empty challenge is an error report, which we must answer
+        // with an empty response to get the final NO from the server.
+        if challenge.is_empty() {
+            format!("user={}\x01auth=Bearer {}\x01\x01", self.user, self.token)
+        } else {
+            String::new()
+        }
+    }
+}
+
+/// SASL OAUTHBEARER, from RFC 7628.
+struct OAuthBearer {
+    user: String,
+    host: String,
+    port: u16,
+    token: String,
+}
+
+impl Authenticator for OAuthBearer {
+    type Response = String;
+
+    fn process(&self, challenge: &[u8]) -> String {
+        // Errors are answered with a dummy response, see RFC 7628 3.2.3
+        if challenge.is_empty() {
+            format!(
+                "n,a={},\x01host={}\x01port={}\x01auth=Bearer {}\x01\x01",
+                self.user, self.host, self.port, self.token
+            )
+        } else {
+            "\x01".to_string()
+        }
+    }
+}
+
+/// Why we can't use a mechanism, if we can't.
+fn unusable_reason(
+    config: &Account,
+    capabilities: &[String],
+    mechanism: AuthMechanism,
+) -> Option<&'static str> {
+    let has = |c: &str| capabilities.iter().any(|cap| cap == c);
+    let (offered, credentials) = match mechanism {
+        AuthMechanism::Login => (!has("LOGINDISABLED"), config.has_password()),
+        AuthMechanism::Plain => (has("AUTH=PLAIN"), config.has_password()),
+        AuthMechanism::XOAuth2 => (has("AUTH=XOAUTH2"), config.oauth_token_command.is_some()),
+        AuthMechanism::OAuthBearer => (
+            has("AUTH=OAUTHBEARER"),
+            config.oauth_token_command.is_some(),
+        ),
+    };
+    if !offered {
+        Some("not offered by the server")
+    } else if !credentials {
+        match mechanism {
+            AuthMechanism::Login | AuthMechanism::Plain => Some("no password"),
+            _ => Some("no oauth_token_command"),
+        }
+    } else {
+        None
+    }
+}
+
+/// What the server offers, for error messages.
+fn offered(capabilities: &[String]) -> String {
+    let mut offered = Vec::new();
+    if !capabilities.iter().any(|c| c == "LOGINDISABLED") {
+        offered.push("LOGIN");
+    }
+    offered.extend(capabilities.iter().filter_map(|c| c.strip_prefix("AUTH=")));
+    if offered.is_empty() {
+        "nothing".to_string()
+    } else {
+        offered.join(", ")
+    }
+}
+
+/// Log in to the server with the first mechanism in the account's
+/// `auth_mechanisms` that the server offers and that we have
+/// credentials for. The capabilities are those from before login.
+pub fn authenticate(
+    client: Client<Connection>,
+    config: &Account,
+    capabilities: &[String],
+) -> Result<Session<Connection>, String> {
+    let mut reasons = Vec::new();
+    let mut mechanism = None;
+    for m in config.auth_mechanisms() {
+        match unusable_reason(config, capabilities, m) {
+            Some(reason) => reasons.push(format!("{} ({})", m.name(), reason)),
+            None => {
+                mechanism = Some(m);
+                break;
+            }
+        }
+    }
+    let mechanism = mechanism.ok_or_else(|| {
+        format!(
+            "No usable authentication mechanism: {}. The server offers: {}",
+            reasons.join(", "),
+            offered(capabilities)
+        )
+    })?;
+
+    // If the server rejects credentials from a command, they may have
+    // changed since we cached them, so get them again and retry once.
+    let mut client = client;
+    let mut retried = false;
+    loop {
+        match login(client, config, mechanism)? {
+            Ok(session) => return Ok(session),
+            Err((e, c)) => {
+                if !retried && is_rejected(&e) && config.forget_credentials() {
+                    retried = true;
+                    client = c;
+                    continue;
+                }
+                return Err(format!("{} authentication failed: {}", mechanism.name(), e));
+            }
+        }
+    }
+}
+
+/// Did the server reject our credentials, rather than fail in some other way?
+fn is_rejected(e: &Error) -> bool {
+    // imap-proto does not know this response code, so it is left in the text
+    match e {
+        Error::No(no) => no.information.starts_with("[AUTHENTICATIONFAILED]"),
+        _ => false,
+    }
+}
+
+/// The result of a login attempt. On failure we get the client back.
+type LoginResult = Result<Session<Connection>, (Error, Client<Connection>)>;
+
+/// Get the credentials for the mechanism and try to log in with them.
+/// The outer error is for failing to get the credentials.
+fn login(
+    client: Client<Connection>,
+    config: &Account,
+    mechanism: AuthMechanism,
+) -> Result<LoginResult, String> {
+    let user = config.username.clone();
+    Ok(match mechanism {
+        AuthMechanism::Login => client.login(&user, config.password()?),
+        AuthMechanism::Plain => {
+            let password = config.password()?;
+            client.authenticate("PLAIN", &Plain { user, password })
+        }
+        AuthMechanism::XOAuth2 => {
+            let token = config.oauth_token()?;
+            client.authenticate("XOAUTH2", &XOAuth2 { user, token })
+        }
+        AuthMechanism::OAuthBearer => {
+            let auth = OAuthBearer {
+                user,
+                host: config.server.clone(),
+                port: config.port.unwrap(),
+                token: config.oauth_token()?,
+            };
+            client.authenticate("OAUTHBEARER", &auth)
+        }
+    })
+}
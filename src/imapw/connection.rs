@@ -0,0 +1,208 @@
+use super::tls;
+use super::trace::Trace;
+use crate::config::{Account, Tls};
+use imap::extensions::idle::SetReadTimeout;
+use rustls_connector::RustlsConnector;
+use rustls_connector::TlsStream as RustlsStream;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// The transport for a connection to the IMAP server.
+enum Stream {
+    Tls(Box<RustlsStream<TcpStream>>),
+    Plain(TcpStream),
+}
+
+/// A connection to the IMAP server, and its trace if it is traced.
+pub struct Connection {
+    stream: Stream,
+    trace: Option<Trace>,
+}
+
+impl Connection {
+    /// Connect to the server for the given account, using the
+    /// configured TLS mode. The server greeting is read, so the
+    /// connection is ready for the first command. With a timeout,
+    /// connecting, reading and writing give up after that long.
+    pub fn new(
+        config: &Account,
+        trace: Option<Trace>,
+        timeout: Option<Duration>,
+    ) -> Result<Connection, String> {
+        let server = config.server.as_str();
+        let mut tcp = connect(server, config.port.unwrap(), timeout)
+            .map_err(|e| format!("Connection to {:?} failed: {}", server, e))?;
+
+        let stream = match config.tls.unwrap_or(Tls::Implicit) {
+            Tls::Implicit => Connection::handshake(config, tcp)?,
+            Tls::Starttls => {
+                Connection::starttls(&mut tcp)
+                    .map_err(|e| format!("STARTTLS with {:?} failed: {}", server, e))?;
+                // The server does not greet us again after the handshake
+                let stream = Connection::handshake(config, tcp)?;
+                return Ok(Connection { stream, trace });
+            }
+            Tls::Plaintext => {
+                let peer = tcp.peer_addr().map_err(|e| e.to_string())?;
+                if !peer.ip().is_loopback() && !config.allow_plaintext.unwrap_or(false) {
+                    return Err(format!(
+                        "Refusing plaintext connection to {:?} ({}), which is not a loopback \
+                         address. Set allow_plaintext = true to connect anyway.",
+                        server, peer
+                    ));
+                }
+                Stream::Plain(tcp)
+            }
+        };
+        let mut conn = Connection { stream, trace };
+        read_greeting(&mut conn)?;
+        Ok(conn)
+    }
+
+    /// Ask the server for its capabilities before we log in, so that
+    /// we know which authentication mechanisms we can use.
+    pub fn capabilities(&mut self) -> Result<Vec<String>, String> {
+        self.write_all(b"a0 CAPABILITY\r\n")
+            .map_err(|e| e.to_string())?;
+        let mut capabilities = Vec::new();
+        loop {
+            let line = read_line(self)?;
+            if let Some(list) = line.strip_prefix("* CAPABILITY ") {
+                capabilities.extend(list.split_whitespace().map(|c| c.to_uppercase()));
+            } else if let Some(status) = line.strip_prefix("a0 ") {
+                if status.starts_with("OK") {
+                    return Ok(capabilities);
+                }
+                return Err(format!("CAPABILITY failed: {}", status.trim_end()));
+            }
+        }
+    }
+
+    /// Negotiate TLS on the given TCP stream.
+    fn handshake(config: &Account, tcp: TcpStream) -> Result<Stream, String> {
+        let server = config.server.as_str();
+        RustlsConnector::from(tls::client_config(config)?)
+            .connect(server, tcp)
+            .map(|tls| Stream::Tls(Box::new(tls)))
+            .map_err(|e| format!("TLS handshake with {:?} failed: {}", server, e))
+    }
+
+    /// Read the server greeting and upgrade the plaintext connection
+    /// with STARTTLS, leaving it ready for the TLS handshake.
+    ///
+    /// This reads a byte at a time so that we never consume anything
+    /// that belongs to the TLS handshake.
+    fn starttls(tcp: &mut TcpStream) -> Result<(), String> {
+        read_greeting(tcp)?;
+        tcp.write_all(b"a0 STARTTLS\r\n")
+            .map_err(|e| e.to_string())?;
+        loop {
+            let line = read_line(tcp)?;
+            if let Some(status) = line.strip_prefix("a0 ") {
+                if status.starts_with("OK") {
+                    return Ok(());
+                }
+                return Err(status.trim_end().to_string());
+            }
+        }
+    }
+
+    /// A handle to the underlying socket, which can be used to shut
+    /// the connection down from another thread.
+    pub fn try_clone_tcp(&self) -> Result<TcpStream, String> {
+        self.tcp().try_clone().map_err(|e| e.to_string())
+    }
+
+    fn tcp(&self) -> &TcpStream {
+        match &self.stream {
+            Stream::Tls(tls) => tls.get_ref(),
+            Stream::Plain(tcp) => tcp,
+        }
+    }
+}
+
+/// Open a TCP connection to the server. With a timeout, each of its
+/// addresses is tried for that long, and the timeout is then set for
+/// reads and writes.
+fn connect(server: &str, port: u16, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return TcpStream::connect((server, port)),
+    };
+    let mut last_error = None;
+    for addr in (server, port).to_socket_addrs()? {
+        match TcpStream::connect_timeout(&addr, timeout) {
+            Ok(tcp) => {
+                tcp.set_read_timeout(Some(timeout))?;
+                tcp.set_write_timeout(Some(timeout))?;
+                return Ok(tcp);
+            }
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| io::Error::other("no addresses found")))
+}
+
+/// Read the server greeting, which must be OK since we always log in.
+fn read_greeting<R: Read>(stream: &mut R) -> Result<(), String> {
+    let greeting = read_line(stream)?;
+    if !greeting.starts_with("* OK") {
+        return Err(format!("Unexpected greeting: {}", greeting.trim_end()));
+    }
+    Ok(())
+}
+
+/// Read a single CRLF terminated line from the stream.
+fn read_line<R: Read>(stream: &mut R) -> Result<String, String> {
+    let mut line = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        stream.read_exact(&mut byte).map_err(|e| e.to_string())?;
+        line.push(byte[0]);
+        if byte[0] == b'\n' {
+            return Ok(String::from_utf8_lossy(&line).to_string());
+        }
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match &mut self.stream {
+            Stream::Tls(tls) => tls.read(buf),
+            Stream::Plain(tcp) => tcp.read(buf),
+        }?;
+        if let Some(trace) = &mut self.trace {
+            trace.server(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = match &mut self.stream {
+            Stream::Tls(tls) => tls.write(buf),
+            Stream::Plain(tcp) => tcp.write(buf),
+        }?;
+        if let Some(trace) = &mut self.trace {
+            trace.client(&buf[..n]);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.stream {
+            Stream::Tls(tls) => tls.flush(),
+            Stream::Plain(tcp) => tcp.flush(),
+        }
+    }
+}
+
+impl SetReadTimeout for Connection {
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> imap::error::Result<()> {
+        self.tcp()
+            .set_read_timeout(timeout)
+            .map_err(imap::error::Error::Io)
+    }
+}
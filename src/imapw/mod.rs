@@ -1,11 +1,18 @@
+mod auth;
+mod connection;
+mod tls;
+mod trace;
+
+use self::connection::Connection;
+pub use self::tls::parse_fingerprint;
+pub use self::trace::Trace;
 use crate::config::Account;
-use imap::extensions::idle;
+use imap::extensions::idle::{self, WaitOutcome};
 use imap::types::{Fetch, Flag, Mailbox, Name, Uid, UnsolicitedResponse, ZeroCopy};
+use imap::Client;
 use imap::Session;
-use imap::{Client, ClientBuilder};
-use rustls_connector::TlsStream as RustlsStream;
 use std::convert::From;
-use std::net::TcpStream;
+use std::net::{Shutdown, TcpStream};
 use std::ops::Deref;
 use std::time::Duration;
 use std::vec::Vec;
@@ -31,7 +38,7 @@ impl<'a> UidResult<'a> {
     pub fn internal_date_millis(&self) -> i64 {
         self.fetch.internal_date().unwrap().timestamp_millis()
     }
-    pub fn flags(&self) -> &[Flag] {
+    pub fn flags(&self) -> &[Flag<'_>] {
         self.fetch.flags()
     }
 }
@@ -47,18 +54,45 @@ impl<'a> From<&'a Fetch> for FetchResult<'a> {
     }
 }
 
+/// How often an IDLE is ended and issued again, so that the server does
+/// not log us off as inactive.
+pub const IDLE_KEEPALIVE: Duration = Duration::from_secs(10 * 60);
+
+/// Interrupts a connection from another thread. See `Imap::interrupter`.
+pub struct Interrupter(TcpStream);
+
+impl Interrupter {
+    pub fn interrupt(&self) {
+        self.0.shutdown(Shutdown::Both).ok();
+    }
+}
+
 pub struct Imap {
-    session: Session<RustlsStream<TcpStream>>,
+    session: Session<Connection>,
+    tcp: TcpStream,
     mailbox: Option<String>,
     qresync: bool,
 }
 
 impl Imap {
-    pub fn new(config: &Account) -> Result<Imap, String> {
-        let client = Imap::connect(config)?;
-        let mut session = client
-            .login(config.username.as_str(), config.password.as_ref().unwrap())
-            .map_err(|e| format!("Login failed: {:?}", e.0))?;
+    /// Connect and log in to the server for the given account. If there
+    /// is a trace, then the conversation with the server is written to it.
+    pub fn new(config: &Account, trace: Option<Trace>) -> Result<Imap, String> {
+        Imap::with_timeout(config, trace, None)
+    }
+
+    /// Like `new`, but give up on the server if it does not answer
+    /// within `timeout`. This is for short connections that must not
+    /// hang, since IDLE waits much longer for an answer.
+    pub fn with_timeout(
+        config: &Account,
+        trace: Option<Trace>,
+        timeout: Option<Duration>,
+    ) -> Result<Imap, String> {
+        let mut conn = Connection::new(config, trace, timeout)?;
+        let capabilities = conn.capabilities()?;
+        let tcp = conn.try_clone_tcp()?;
+        let mut session = auth::authenticate(Client::new(conn), config, &capabilities)?;
 
         let capabilities = session
             .capabilities()
@@ -81,22 +115,12 @@ impl Imap {
 
         Ok(Imap {
             session,
+            tcp,
             mailbox: None,
             qresync: capabilities.deref().has_str("QRESYNC"),
         })
     }
 
-    #[allow(dead_code)]
-    pub fn debug(&mut self, enable: bool) {
-        self.session.debug = enable;
-    }
-
-    fn connect(config: &Account) -> Result<Client<RustlsStream<TcpStream>>, String> {
-        ClientBuilder::new(&config.server, config.port.unwrap())
-            .rustls()
-            .map_err(|e| format!("Connection to {:?} failed: {}", &config.server, e))
-    }
-
     pub fn list(
         &mut self,
         reference_name: Option<&str>,
@@ -107,7 +131,10 @@ impl Imap {
             .map_err(|e| format!("LIST failed: {}", e))
     }
 
-    pub fn idle(&mut self) -> Result<(), String> {
+    /// IDLE until the mailbox changes, issuing the IDLE again every
+    /// `IDLE_KEEPALIVE`. `alive` is called every time, to show that we
+    /// are not stuck.
+    pub fn idle<F: FnMut()>(&mut self, mut alive: F) -> Result<(), String> {
         /* IDLE Builder - not released yet
         self.session
             .idle()
@@ -116,15 +143,27 @@ impl Imap {
             .map_err(|e| format!("{}", e))
             .map(|_| ())
         */
-        self.session
-            .idle()
-            .map_err(|e| format!("{}", e))
-            .and_then(|mut i| {
-                i.set_keepalive(Duration::from_secs(10 * 60));
-                i.wait_keepalive_while(idle::stop_on_any)
-                    .map_err(|e| format!("{}", e))
-            })
-            .map(|_| ())
+        loop {
+            alive();
+            let outcome = self
+                .session
+                .idle()
+                .map_err(|e| format!("{}", e))?
+                .wait_with_timeout_while(IDLE_KEEPALIVE, idle::stop_on_any)
+                .map_err(|e| format!("{}", e))?;
+            if let WaitOutcome::MailboxChanged = outcome {
+                return Ok(());
+            }
+        }
+    }
+
+    /// A handle that can interrupt a blocking call like `idle` from
+    /// another thread, by shutting down the connection.
+    pub fn interrupter(&self) -> Result<Interrupter, String> {
+        self.tcp
+            .try_clone()
+            .map(Interrupter)
+            .map_err(|e| e.to_string())
     }
 
     pub fn fetch_uid(&mut self, uid: u32) -> Result<ZeroCopy<Vec<Fetch>>, String> {
@@ -142,6 +181,13 @@ impl Imap {
             .map_err(|e| format!("UID FETCH failed: {}", e))
     }
 
+    pub fn fetch_uid_set_meta(&mut self, uids: &[u32]) -> Result<ZeroCopy<Vec<Fetch>>, String> {
+        let set: Vec<String> = uids.iter().map(|uid| uid.to_string()).collect();
+        self.session
+            .uid_fetch(set.join(","), "(UID RFC822.SIZE INTERNALDATE FLAGS)")
+            .map_err(|e| format!("UID FETCH failed: {}", e))
+    }
+
     pub fn fetch_uids(
         &mut self,
         first: u32,
@@ -181,9 +227,18 @@ impl Imap {
         self.session
             .select(mailbox)
             .map_err(|e| format!("SELECT {} failed: {}", mailbox, e))
-            .map(|mbox| {
+            .inspect(|_| {
+                self.mailbox = Some(mailbox.to_string());
+            })
+    }
+
+    /// Select the mailbox read-only, so that nothing is changed on the server.
+    pub fn examine_mailbox(&mut self, mailbox: &str) -> Result<Mailbox, String> {
+        self.session
+            .examine(mailbox)
+            .map_err(|e| format!("EXAMINE {} failed: {}", mailbox, e))
+            .inspect(|_| {
                 self.mailbox = Some(mailbox.to_string());
-                mbox
             })
     }
 
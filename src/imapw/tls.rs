@@ -0,0 +1,174 @@
+use crate::config::Account;
+use ring::digest::{digest, SHA256};
+use rustls_connector::rustls::internal::pemfile;
+use rustls_connector::rustls::{
+    Certificate, ClientConfig, PrivateKey, RootCertStore, ServerCertVerified, ServerCertVerifier,
+    TLSError,
+};
+use rustls_connector::rustls_native_certs;
+use rustls_connector::webpki::DNSNameRef;
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+/// Build the TLS configuration for the given account.
+///
+/// Server certificates are verified against the system roots, or against
+/// `ca_file` if it is set. If `fingerprint` is set, then the server certificate
+/// must match it instead, and is not checked against any roots.
+pub fn client_config(config: &Account) -> Result<ClientConfig, String> {
+    let mut tls = ClientConfig::new();
+
+    tls.root_store = match &config.ca_file {
+        Some(path) => {
+            let mut store = RootCertStore::empty();
+            let (valid, _) = store
+                .add_pem_file(&mut open(path)?)
+                .map_err(|_| format!("Could not parse certificates in ca_file {}", path))?;
+            if valid == 0 {
+                return Err(format!("No valid certificates in ca_file {}", path));
+            }
+            store
+        }
+        None => match rustls_native_certs::load_native_certs() {
+            Ok(store) => store,
+            // Use what we could load, like rustls_connector does.
+            Err((Some(store), _)) => store,
+            Err((None, e)) => return Err(format!("Could not load system certificates: {}", e)),
+        },
+    };
+
+    match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => {
+            let certs = pemfile::certs(&mut open(cert)?)
+                .map_err(|_| format!("Could not parse client_cert {}", cert))?;
+            let key = private_key(key)?;
+            tls.set_single_client_cert(certs, key)
+                .map_err(|e| format!("Invalid client certificate: {}", e))?;
+        }
+        (None, None) => (),
+        _ => return Err("client_cert and client_key must be set together".to_string()),
+    }
+
+    if let Some(fingerprint) = &config.fingerprint {
+        let verifier = PinnedCertVerifier {
+            fingerprint: parse_fingerprint(fingerprint)?,
+        };
+        tls.dangerous().set_certificate_verifier(Arc::new(verifier));
+    }
+
+    Ok(tls)
+}
+
+fn open(path: &str) -> Result<BufReader<File>, String> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|e| format!("Could not open {}: {}", path, e))
+}
+
+/// Read the first private key in the given file, in either PKCS#8 or RSA format.
+fn private_key(path: &str) -> Result<PrivateKey, String> {
+    let mut keys = pemfile::pkcs8_private_keys(&mut open(path)?).unwrap_or_default();
+    if keys.is_empty() {
+        keys = pemfile::rsa_private_keys(&mut open(path)?).unwrap_or_default();
+    }
+    keys.into_iter()
+        .next()
+        .ok_or_else(|| format!("No private key found in client_key {}", path))
+}
+
+/// Parse a SHA-256 fingerprint in hex, with or without colons.
+pub fn parse_fingerprint(fingerprint: &str) -> Result<Vec<u8>, String> {
+    let hex = fingerprint.replace(':', "");
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            // from_str_radix would also take a sign
+            hex.get(i..i + 2)
+                .filter(|b| b.chars().all(|c| c.is_ascii_hexdigit()))
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+        })
+        .collect::<Option<Vec<u8>>>();
+    match bytes {
+        Some(bytes) if bytes.len() == SHA256.output_len => Ok(bytes),
+        _ => Err(format!(
+            "Invalid fingerprint {:?}: expected a SHA-256 digest in hex",
+            fingerprint
+        )),
+    }
+}
+
+/// Format a fingerprint the way `openssl x509 -fingerprint -sha256` does.
+fn format_fingerprint(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+/// Accepts a server certificate only if its SHA-256 fingerprint matches.
+struct PinnedCertVerifier {
+    fingerprint: Vec<u8>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &RootCertStore,
+        presented_certs: &[Certificate],
+        _dns_name: DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<ServerCertVerified, TLSError> {
+        let cert = presented_certs
+            .first()
+            .ok_or(TLSError::NoCertificatesPresented)?;
+        let actual = digest(&SHA256, &cert.0);
+        if actual.as_ref() == self.fingerprint.as_slice() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(TLSError::General(format!(
+                "Server certificate fingerprint {} does not match",
+                format_fingerprint(actual.as_ref())
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEX: &str = "8f434346648f6b96df89dda901c5176b10a6d83961dd3c1ac88b59b2dc327aa4";
+
+    #[test]
+    fn fingerprint_in_hex() {
+        let bytes = parse_fingerprint(HEX).unwrap();
+        assert_eq!(bytes.len(), 32);
+        assert_eq!(bytes[..3], [0x8f, 0x43, 0x43]);
+        assert_eq!(parse_fingerprint(&HEX.to_uppercase()).unwrap(), bytes);
+    }
+
+    #[test]
+    fn fingerprint_with_colons() {
+        let bytes = parse_fingerprint(HEX).unwrap();
+        let formatted = format_fingerprint(&bytes);
+        assert!(formatted.starts_with("8F:43:43:46:"));
+        assert_eq!(parse_fingerprint(&formatted).unwrap(), bytes);
+    }
+
+    #[test]
+    fn invalid_fingerprints() {
+        // SHA-1 is too short
+        assert!(parse_fingerprint("da39a3ee5e6b4b0d3255bfef95601890afd80709").is_err());
+        assert!(parse_fingerprint(&HEX[1..]).is_err());
+        assert!(parse_fingerprint(&format!("{}00", HEX)).is_err());
+        assert!(parse_fingerprint(&HEX.replace('8', "g")).is_err());
+        assert!(parse_fingerprint(&format!("+f{}", &HEX[2..])).is_err());
+        assert!(parse_fingerprint("").is_err());
+        assert_eq!(
+            parse_fingerprint("ab:cd").unwrap_err(),
+            "Invalid fingerprint \"ab:cd\": expected a SHA-256 digest in hex"
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use chrono::prelude::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Writes the IMAP conversation on a connection to a file, for bug
+/// reports. Credentials are left out, and so is the content of every
+/// literal, which covers message bodies, so only the sizes are shown.
+pub struct Trace {
+    file: File,
+    /// Tells apart the connections that share a trace file.
+    label: String,
+    client: Half,
+    server: Half,
+    /// The client is sending credentials, until the server answers.
+    redacting: bool,
+}
+
+/// What is sent one way on a connection, split into lines.
+#[derive(Default)]
+struct Half {
+    line: Vec<u8>,
+    /// How many bytes of a literal are still to come.
+    literal: usize,
+    /// How big that literal is.
+    literal_size: usize,
+}
+
+impl Half {
+    /// Add data, and return the lines it completes, with any literals
+    /// replaced by their size.
+    fn feed(&mut self, mut data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        while !data.is_empty() {
+            if self.literal > 0 {
+                let n = self.literal.min(data.len());
+                self.literal -= n;
+                data = &data[n..];
+                if self.literal == 0 {
+                    self.line
+                        .extend_from_slice(format!("<{} bytes>", self.literal_size).as_bytes());
+                }
+                continue;
+            }
+            match data.iter().position(|b| *b == b'\n') {
+                Some(end) => {
+                    self.line.extend_from_slice(&data[..=end]);
+                    data = &data[end + 1..];
+                    let line = String::from_utf8_lossy(&self.line).trim_end().to_string();
+                    self.line.clear();
+                    self.literal = literal_size(&line).unwrap_or(0);
+                    self.literal_size = self.literal;
+                    lines.push(line);
+                }
+                None => {
+                    self.line.extend_from_slice(data);
+                    break;
+                }
+            }
+        }
+        lines
+    }
+}
+
+/// The size of the literal that follows a line ending with {size}, or
+/// {size+} for a non-synchronizing literal.
+fn literal_size(line: &str) -> Option<usize> {
+    let start = line.rfind('{')?;
+    line[start + 1..]
+        .strip_suffix('}')?
+        .trim_end_matches('+')
+        .parse()
+        .ok()
+}
+
+impl Trace {
+    /// Append the trace of a connection to the file at `path`. Each line
+    /// is marked with `label`.
+    pub fn open(path: &Path, label: &str) -> Result<Trace, String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        Ok(Trace {
+            file,
+            label: label.to_string(),
+            client: Half::default(),
+            server: Half::default(),
+            redacting: false,
+        })
+    }
+
+    /// Trace data sent by the client.
+    pub fn client(&mut self, data: &[u8]) {
+        for line in self.client.feed(data) {
+            let line = self.redact(line);
+            self.write("C", &line);
+        }
+    }
+
+    /// Trace data sent by the server.
+    pub fn server(&mut self, data: &[u8]) {
+        for line in self.server.feed(data) {
+            // Anything but a continuation request ends the login
+            if !line.starts_with('+') {
+                self.redacting = false;
+            }
+            self.write("S", &line);
+        }
+    }
+
+    /// Leave out the credentials from LOGIN and AUTHENTICATE, including
+    /// the rest of the exchange with the server.
+    fn redact(&mut self, line: String) -> String {
+        if self.redacting {
+            return "<redacted>".to_string();
+        }
+        let mut words = line.splitn(4, ' ');
+        let tag = words.next().unwrap_or("");
+        let command = words.next().unwrap_or("").to_uppercase();
+        match command.as_str() {
+            "LOGIN" => {
+                self.redacting = true;
+                format!("{} LOGIN <redacted>", tag)
+            }
+            "AUTHENTICATE" => {
+                self.redacting = true;
+                let mechanism = words.next().unwrap_or("");
+                match words.next() {
+                    Some(_) => format!("{} AUTHENTICATE {} <redacted>", tag, mechanism),
+                    None => format!("{} AUTHENTICATE {}", tag, mechanism),
+                }
+            }
+            _ => line,
+        }
+    }
+
+    fn write(&mut self, direction: &str, line: &str) {
+        writeln!(
+            self.file,
+            "{} {} {}: {}",
+            Local::now().format("%Y-%m-%d %H:%M:%S%.3f"),
+            self.label,
+            direction,
+            line
+        )
+        .ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// A trace in a temporary file, and the path to read it back from.
+    fn trace(name: &str) -> (Trace, PathBuf) {
+        let path = std::env::temp_dir().join(format!("runt-trace-{}-{}", std::process::id(), name));
+        fs::remove_file(&path).ok();
+        (Trace::open(&path, "test").unwrap(), path)
+    }
+
+    /// The traced lines, without the time and label.
+    fn lines(path: &Path) -> Vec<String> {
+        let text = fs::read_to_string(path).unwrap();
+        fs::remove_file(path).ok();
+        text.lines()
+            .map(|line| line.split_once(" test ").unwrap().1.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn literal_sizes() {
+        assert_eq!(literal_size("a1 APPEND INBOX {310}"), Some(310));
+        assert_eq!(literal_size("a1 APPEND INBOX {310+}"), Some(310));
+        assert_eq!(literal_size("* 1 FETCH (BODY[] {0}"), Some(0));
+        assert_eq!(literal_size("a1 LOGIN user pass"), None);
+        assert_eq!(literal_size("a1 LOGIN user {pass}"), None);
+        assert_eq!(literal_size("a1 LOGIN {3} user"), None);
+    }
+
+    #[test]
+    fn lines_split_across_reads() {
+        let mut half = Half::default();
+        assert!(half.feed(b"* OK [CAPA").is_empty());
+        assert_eq!(
+            half.feed(b"BILITY IMAP4rev1] ready\r\n* 1 EX"),
+            ["* OK [CAPABILITY IMAP4rev1] ready"]
+        );
+        assert_eq!(half.feed(b"ISTS\r\n"), ["* 1 EXISTS"]);
+    }
+
+    #[test]
+    fn server_literal_split_across_reads() {
+        let mut half = Half::default();
+        assert_eq!(
+            half.feed(b"* 1 FETCH (UID 4 BODY[] {11}\r\nhel"),
+            ["* 1 FETCH (UID 4 BODY[] {11}"]
+        );
+        assert!(half.feed(b"lo wo").is_empty());
+        assert_eq!(
+            half.feed(b"rld)\r\na4 OK done\r\n"),
+            ["<11 bytes>)", "a4 OK done"]
+        );
+    }
+
+    #[test]
+    fn login_is_redacted() {
+        let (mut trace, path) = trace("login");
+        trace.server(b"* OK ready\r\n");
+        trace.client(b"a1 LOGIN user secret\r\n");
+        trace.server(b"a1 OK logged in\r\n");
+        trace.client(b"a2 SELECT INBOX\r\n");
+        assert_eq!(
+            lines(&path),
+            [
+                "S: * OK ready",
+                "C: a1 LOGIN <redacted>",
+                "S: a1 OK logged in",
+                "C: a2 SELECT INBOX",
+            ]
+        );
+    }
+
+    #[test]
+    fn login_with_literal_password_is_redacted() {
+        let (mut trace, path) = trace("login-literal");
+        trace.client(b"a1 LOGIN user {6}\r\n");
+        trace.server(b"+ go ahead\r\n");
+        trace.client(b"secret\r\n");
+        trace.server(b"a1 OK logged in\r\n");
+        assert_eq!(
+            lines(&path),
+            [
+                "C: a1 LOGIN <redacted>",
+                "S: + go ahead",
+                "C: <redacted>",
+                "S: a1 OK logged in",
+            ]
+        );
+    }
+
+    #[test]
+    fn authenticate_plain_is_redacted() {
+        let (mut trace, path) = trace("authenticate");
+        trace.client(b"a1 AUTHENTICATE PLAIN\r\n");
+        trace.server(b"+ \r\n");
+        trace.client(b"AHVzZXIAc2VjcmV0\r\n");
+        trace.server(b"a1 OK authenticated\r\n");
+        trace.client(b"a2 CAPABILITY\r\n");
+        assert_eq!(
+            lines(&path),
+            [
+                "C: a1 AUTHENTICATE PLAIN",
+                "S: +",
+                "C: <redacted>",
+                "S: a1 OK authenticated",
+                "C: a2 CAPABILITY",
+            ]
+        );
+    }
+
+    #[test]
+    fn authenticate_with_initial_response_is_redacted() {
+        let (mut trace, path) = trace("authenticate-ir");
+        trace.client(b"a1 AUTHENTICATE PLAIN AHVzZXIAc2VjcmV0\r\n");
+        trace.server(b"a1 OK authenticated\r\n");
+        assert_eq!(
+            lines(&path),
+            [
+                "C: a1 AUTHENTICATE PLAIN <redacted>",
+                "S: a1 OK authenticated",
+            ]
+        );
+    }
+
+    #[test]
+    fn append_literal_is_left_out() {
+        let (mut trace, path) = trace("append");
+        trace.client(b"a5 APPEND INBOX (\\Seen) {5}\r\n");
+        trace.server(b"+ go\r\n");
+        trace.client(b"hello");
+        trace.client(b"\r\n");
+        trace.server(b"a5 OK [APPENDUID 1 7] done\r\n");
+        assert_eq!(
+            lines(&path),
+            [
+                "C: a5 APPEND INBOX (\\Seen) {5}",
+                "S: + go",
+                "C: <5 bytes>",
+                "S: a5 OK [APPENDUID 1 7] done",
+            ]
+        );
+    }
+
+    #[test]
+    fn non_synchronizing_literal_is_left_out() {
+        let (mut trace, path) = trace("literal-plus");
+        trace.client(b"a6 APPEND INBOX {5+}\r\nhello\r\n");
+        trace.server(b"a6 OK done\r\n");
+        assert_eq!(
+            lines(&path),
+            ["C: a6 APPEND INBOX {5+}", "C: <5 bytes>", "S: a6 OK done",]
+        );
+    }
+}
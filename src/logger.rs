@@ -0,0 +1,189 @@
+use chrono::prelude::*;
+use libc::c_char;
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use std::env;
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Rotate the log file when it would get bigger than this.
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+/// How many rotated log files to keep, as FILE.1 (the newest) to FILE.N.
+const KEEP_LOGS: usize = 3;
+
+/// Where log messages go.
+enum Output {
+    /// Standard error, with a timestamp on each line. If standard error
+    /// goes to the systemd journal, then each line has a priority prefix
+    /// instead, see sd-daemon(3).
+    Stderr { journal: bool },
+    /// A file that is rotated when it gets too big.
+    File(LogFile),
+    /// The mail facility of syslog.
+    Syslog,
+}
+
+/// A log file, and how big it is so far.
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> Result<LogFile, String> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        Ok(LogFile { path, file, size })
+    }
+
+    /// Append a line, rotating the file first if it would get too big.
+    fn write(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > MAX_LOG_SIZE {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    /// Move FILE to FILE.1, FILE.1 to FILE.2 and so on, dropping the
+    /// oldest, and start a new FILE.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotated = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        for n in (1..KEEP_LOGS).rev() {
+            fs::rename(rotated(n), rotated(n + 1)).ok();
+        }
+        fs::rename(&self.path, rotated(1))?;
+        *self = LogFile::open(self.path.clone()).map_err(io::Error::other)?;
+        Ok(())
+    }
+}
+
+/// Sends log messages to the chosen output.
+struct Logger {
+    output: Mutex<Output>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut output = self.output.lock().unwrap();
+        match &mut *output {
+            Output::Stderr { journal: true } => {
+                let prefix = format!("<{}>", priority(record.level()));
+                let mut stderr = io::stderr().lock();
+                for line in record.args().to_string().lines() {
+                    writeln!(stderr, "{}{}", prefix, line).ok();
+                }
+            }
+            Output::Stderr { journal: false } => {
+                write!(io::stderr().lock(), "{}", format_line(record)).ok();
+            }
+            Output::File(file) => {
+                if let Err(e) = file.write(&format_line(record)) {
+                    eprintln!("Could not write to {}: {}", file.path.display(), e);
+                    eprint!("{}", format_line(record));
+                }
+            }
+            Output::Syslog => {
+                let message = record.args().to_string().replace('\0', "");
+                if let Ok(message) = CString::new(message) {
+                    unsafe {
+                        libc::syslog(
+                            priority(record.level()),
+                            b"%s\0".as_ptr() as *const c_char,
+                            message.as_ptr(),
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    fn flush(&self) {
+        if let Output::File(file) = &mut *self.output.lock().unwrap() {
+            file.file.flush().ok();
+        }
+    }
+}
+
+/// A log line with a timestamp and level.
+fn format_line(record: &Record) -> String {
+    format!(
+        "{} {:<5} {}\n",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        record.level(),
+        record.args()
+    )
+}
+
+/// The syslog priority for a level.
+fn priority(level: Level) -> libc::c_int {
+    match level {
+        Level::Error => libc::LOG_ERR,
+        Level::Warn => libc::LOG_WARNING,
+        Level::Info => libc::LOG_INFO,
+        Level::Debug | Level::Trace => libc::LOG_DEBUG,
+    }
+}
+
+/// Whether standard error is connected to the systemd journal, which
+/// systemd tells us by setting JOURNAL_STREAM to its device and inode.
+fn stderr_is_journal() -> bool {
+    let stream = match env::var("JOURNAL_STREAM") {
+        Ok(stream) => stream,
+        Err(_) => return false,
+    };
+    let mut stat = std::mem::MaybeUninit::<libc::stat>::uninit();
+    if unsafe { libc::fstat(io::stderr().as_raw_fd(), stat.as_mut_ptr()) } != 0 {
+        return false;
+    }
+    let stat = unsafe { stat.assume_init() };
+    stream == format!("{}:{}", stat.st_dev, stat.st_ino)
+}
+
+/// Send log messages up to the given level to `output`, which is
+/// "stderr", "syslog", or the path of a log file.
+pub fn init(level: LevelFilter, output: &str) -> Result<(), String> {
+    let output = match output {
+        "stderr" => Output::Stderr {
+            journal: stderr_is_journal(),
+        },
+        "syslog" => {
+            unsafe {
+                libc::openlog(
+                    b"runt\0".as_ptr() as *const c_char,
+                    libc::LOG_PID,
+                    libc::LOG_MAIL,
+                );
+            }
+            Output::Syslog
+        }
+        path => Output::File(LogFile::open(PathBuf::from(path))?),
+    };
+    log::set_boxed_logger(Box::new(Logger {
+        output: Mutex::new(output),
+    }))
+    .map_err(|e| e.to_string())?;
+    log::set_max_level(level);
+    Ok(())
+}
@@ -2,7 +2,7 @@ use crate::cache::MessageMeta;
 use maildir::MailEntry;
 use maildir::Maildir as SubMaildir;
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 //use std::time::SystemTime;
 
 /// A wrapper around a maildir implementation
@@ -62,18 +62,23 @@ fn meta_equal(maildir_meta: &MailEntry, cache_meta: &MessageMeta) -> Result<bool
 }
 
 impl Maildir {
-    /// Make a new Maildir for the given root directory, account, and mailbox.
-    pub fn new(root: &str, account: &str, mailbox: &str) -> Result<Maildir, String> {
-        let mut maildirpath = PathBuf::from(root);
-        maildirpath.push(account);
-        maildirpath.push(mailbox);
-        let maildir = SubMaildir::from(maildirpath);
+    /// Make a new Maildir at the given path.
+    pub fn new(path: &Path) -> Result<Maildir, String> {
+        let maildir = SubMaildir::from(path.to_path_buf());
         maildir
             .create_dirs()
             .map_err(|e| format!("Could not create maildir structure: {}", e))?;
         Ok(Maildir { maildir })
     }
 
+    /// Use the Maildir at the given path without creating it. A Maildir
+    /// that does not exist yet is empty.
+    pub fn existing(path: &Path) -> Maildir {
+        Maildir {
+            maildir: SubMaildir::from(path.to_path_buf()),
+        }
+    }
+
     /// Get the path to the Maildir
     pub fn path(&self) -> PathBuf {
         self.maildir.path().to_path_buf()
@@ -89,6 +94,11 @@ impl Maildir {
         .map_err(|e| format!("Message store failed: {}", e))
     }
 
+    /// The file of the message with the given ID, if it is in the Maildir.
+    pub fn message_path(&self, id: &str) -> Option<PathBuf> {
+        self.maildir.find(id).map(|entry| entry.path().clone())
+    }
+
     /// Move a message ID to the cur Maildir directory and set its flags.
     pub fn move_message_to_cur(&mut self, id: &str, flags: &str) -> Result<(), String> {
         self.maildir
@@ -124,7 +134,7 @@ impl Maildir {
 
             if let Some(cache_meta) = cache.get(mailentry.id()) {
                 // If the meta is different then add it to the changed list
-                if !meta_equal(&mailentry, &cache_meta)? {
+                if !meta_equal(&mailentry, cache_meta)? {
                     changed.push(mailentry.id().to_string());
                 }
 
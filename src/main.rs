@@ -3,7 +3,10 @@ extern crate chrono;
 extern crate dirs_next;
 extern crate imap;
 extern crate libc;
+#[macro_use]
+extern crate log;
 extern crate maildir;
+extern crate mailparse;
 extern crate notify;
 extern crate regex;
 extern crate rusqlite;
@@ -13,121 +16,196 @@ extern crate toml;
 extern crate serde_derive;
 extern crate rustls_connector;
 
+mod activity;
 mod cache;
+mod cli;
 mod config;
+mod control;
 mod imapw;
+mod logger;
 mod maildirw;
+mod metrics;
+mod newmail;
+mod runner;
+mod signals;
 mod syncdir;
+mod systemd;
+use cli::{Command, Options};
 use config::Config;
-use imapw::Imap;
-use libc::SIGINT;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::thread::{sleep, spawn};
-use std::time;
-use syncdir::{SyncDir, SyncMessage};
-
-static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+use runner::{Event, Runner};
+use std::sync::mpsc::channel;
+use systemd::Notifier;
 
 fn main() {
-    // set up signal handler for Ctrl-C
-    unsafe {
-        libc::signal(SIGINT, handle_sigint as usize);
+    let opts = match Options::parse(std::env::args().skip(1)) {
+        Ok(opts) => opts,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, Options::usage());
+            std::process::exit(2);
+        }
+    };
+
+    match opts.command {
+        Command::Help => {
+            println!("{}", Options::usage());
+            return;
+        }
+        Command::Version => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Command::Sync | Command::CheckConfig | Command::Ctl => (),
     }
 
-    let mut threads = vec![];
-    let mut notifications = vec![];
+    let (events_tx, events) = channel();
+    if opts.command == Command::Sync {
+        let output = opts.log.as_deref().unwrap_or("stderr");
+        if let Err(e) = logger::init(opts.log_level(), output) {
+            eprintln!("Could not start logging: {}", e);
+            std::process::exit(2);
+        }
+        // Handle signals in a thread of their own, before any other
+        // thread starts, so that they all leave the signals to it. When
+        // only syncing once we let the default handlers terminate the
+        // process.
+        if !opts.once {
+            signals::handle(events_tx.clone());
+        }
+        if let Some(output) = &opts.events {
+            if let Err(e) = activity::init(output) {
+                eprintln!("Could not open the activity stream: {}", e);
+                std::process::exit(2);
+            }
+        }
+        if let Some(target) = &opts.metrics {
+            if let Err(e) = metrics::init(target) {
+                eprintln!("Could not export metrics: {}", e);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    let path = opts.config.clone().unwrap_or_else(Config::default_path);
 
-    // Parse out config and set up sync jobs
-    let configs = Config::new();
-    for config in configs.accounts {
-        let mut imap = Imap::new(&config).unwrap();
-        let mut idle_mailboxes = Vec::new();
-        let mut pool_mailboxes = Vec::new();
-        match imap.list(None, Some("*")) {
-            Ok(listing) => {
-                for mailbox in listing.iter() {
-                    if !mailbox
-                        .attributes()
-                        .contains(&imap::types::NameAttribute::NoSelect)
-                        && !config.is_mailbox_excluded(mailbox.name())
-                    {
-                        // select it and sync
-                        match SyncDir::new(&config, mailbox.name().to_string()) {
-                            Err(e) => panic!("Sync failed: {}", e),
-                            Ok(sd) => {
-                                notifications.push(sd.sender.clone());
-                                if sd.should_idle() {
-                                    idle_mailboxes.push(sd);
-                                } else {
-                                    pool_mailboxes.push(sd);
-                                }
-                            }
-                        }
+    // All ctl needs from the config is where the socket is
+    if opts.command == Command::Ctl {
+        let socket = match &opts.socket {
+            Some(socket) => socket.clone(),
+            None => match Config::read_state_dir(&path) {
+                Ok(dir) => dir.join("runt.sock"),
+                Err(errors) => {
+                    for e in errors {
+                        eprintln!("{}", e);
                     }
+                    std::process::exit(1);
                 }
-            }
-            Err(e) => println!("Error getting listing: {}", e),
+            },
         };
-        imap.logout().ok();
-
-        // Handle if the user has specified some maximum number of threads
-        // to run with. We have to allocate one thread for every idle
-        // mailbox, and remaining threads do all of the sync-once mailboxes.
-        let mut pool_size = pool_mailboxes.len();
-        if let Some(max_threads) = config.max_concurrency {
-            if let Some(pool) = max_threads.checked_sub(idle_mailboxes.len()) {
-                pool_size = pool;
-            } else {
-                pool_size = 0;
+        match control::send(&socket, &opts.request.join(" ")) {
+            Ok(true) => return,
+            Ok(false) => std::process::exit(1),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(2);
             }
+        }
+    }
 
-            if pool_size == 0 && !pool_mailboxes.is_empty() {
-                println!("Account {}.max_concurrency ({}) is too small for the number of idle mailboxes ({}) and non-idle mailboxes.", config.account, max_threads, idle_mailboxes.len(), );
-                println!("You may see errors from the server and some mailboxes may not be synchronized.\nTo fix this, specify a number of mailboxes to idle that is smaller that max_concurrency, or increase max_concurrency if possible.");
-                pool_size = 1;
+    // Parse out config
+    let configs = match Config::new(&path) {
+        Ok(configs) => configs,
+        Err(errors) => {
+            for e in errors {
+                eprintln!("{}", e);
             }
+            std::process::exit(1);
         }
+    };
+    if opts.command == Command::CheckConfig {
+        println!(
+            "{}: OK, {} account(s)",
+            path.display(),
+            configs.accounts.len()
+        );
+        return;
+    }
+    let socket = opts
+        .socket
+        .clone()
+        .unwrap_or_else(|| configs.state_dir().join("runt.sock"));
+    for name in &opts.accounts {
+        if !configs.accounts.iter().any(|a| &a.account == name) {
+            eprintln!("No account named {:?} in config", name);
+            std::process::exit(2);
+        }
+    }
 
-        idle_mailboxes.into_iter().for_each(|mut sd| {
-            threads.push(spawn(move || sd.sync()));
-        });
-
-        if !pool_mailboxes.is_empty() {
-            if let Ok(pool) = rayon::ThreadPoolBuilder::new()
-                .num_threads(pool_size)
-                .build()
-            {
-                pool_mailboxes.into_iter().for_each(|mut sd| {
-                    pool.spawn(move || {
-                        if let Err(e) = sd.sync() {
-                            eprintln!("Synchronize-once for mailbox {} failed: {}", sd.mailbox, e);
-                        }
-                    })
-                });
+    // When only syncing once there is nothing to tell systemd or the
+    // control socket about. The socket is only removed if we bound it.
+    let listening = if opts.once {
+        None
+    } else {
+        match control::listen(&socket, events_tx.clone()) {
+            Ok(listening) => Some(listening),
+            Err(e) => {
+                warn!("Not listening on the control socket: {}", e);
+                None
             }
         }
+    };
+
+    // Set up sync jobs
+    let mut runner = Runner::new(&opts, &path, events_tx);
+    if let Err(e) = runner.apply(&configs) {
+        eprintln!("{}", e);
+        drop(listening);
+        std::process::exit(2);
     }
+    let notifier = if opts.once {
+        None
+    } else {
+        Notifier::new(runner.statuses())
+    };
 
-    // spin off the thread to wait for Ctrl-C
-    threads.push(spawn(move || {
-        while !SHUTDOWN.load(Ordering::Relaxed) {
-            sleep(time::Duration::from_millis(1000));
+    // When syncing once, every mailbox is in a pool, so wait for
+    // them all and report how things went.
+    if opts.once {
+        let failed = runner.wait();
+        activity::flush();
+        if let Some(target) = &opts.metrics {
+            metrics::flush(target);
         }
-        for s in notifications {
-            s.send(SyncMessage::Exit).ok();
+        if failed > 0 {
+            error!("Synchronization failed for {} mailbox(es)", failed);
+            std::process::exit(1);
         }
-        Ok(())
-    }));
+        return;
+    }
 
-    for t in threads {
-        if let Err(what) = t.join().unwrap() {
-            eprintln!("Error joining sync thread: {}", what);
+    for event in events {
+        match event {
+            Event::Shutdown => {
+                info!("Shutting down...");
+                break;
+            }
+            Event::Reload => runner.reload(None),
+            Event::Reloaded(plan) => runner.reloaded(plan),
+            Event::Stopped => runner.stopped(),
+            Event::Finished(account, mailbox) => runner.finished(account, mailbox),
+            Event::Sync => runner.sync_all(),
+            Event::Status => runner.log_status(),
+            Event::Pause => runner.pause_all(),
+            Event::Resume => runner.resume_all(),
+            Event::Request(request, reply) => runner.handle(request, reply),
         }
     }
-}
-
-#[allow(dead_code)]
-fn handle_sigint(_signal: i32) {
-    println!("Shutting down...");
-    SHUTDOWN.store(true, Ordering::Relaxed);
+    if let Some(notifier) = &notifier {
+        notifier.stopping();
+    }
+    runner.stop_all();
+    activity::flush();
+    if let Some(target) = &opts.metrics {
+        metrics::flush(target);
+    }
+    drop(listening);
 }
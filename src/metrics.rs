@@ -0,0 +1,334 @@
+use crate::activity::Activity;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::thread::{sleep, spawn};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How often the metrics file is rewritten.
+const WRITE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long to wait for an HTTP client to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What we count for a mailbox.
+#[derive(Default)]
+pub struct Counters {
+    pub downloads: u64,
+    pub downloaded_bytes: u64,
+    pub appends: u64,
+    pub local_deletes: u64,
+    pub server_deletes: u64,
+    pub local_flags: u64,
+    pub server_flags: u64,
+    /// IMAP connections opened, for sync passes and IDLE.
+    pub connections: u64,
+    /// IDLEs that ended because the mailbox changed on the server.
+    pub idle_wakeups: u64,
+    pub sync_passes: u64,
+    pub sync_seconds: f64,
+    /// Sync passes that failed, including failures to start IDLE
+    /// after a pass.
+    pub sync_failures: u64,
+    /// The last sync pass failed, so we are waiting to retry it.
+    pub failing: bool,
+    /// When the last sync pass finished without errors, in seconds
+    /// since the epoch.
+    pub last_success: Option<f64>,
+}
+
+/// The counters of every mailbox, by account and mailbox name, if
+/// metrics are being exported.
+static METRICS: OnceLock<Mutex<BTreeMap<(String, String), Counters>>> = OnceLock::new();
+
+/// Where metrics are exported to.
+enum Target {
+    /// Answer HTTP requests for /metrics on this address.
+    Http(SocketAddr),
+    /// Rewrite this file regularly, for the textfile collector of the
+    /// node exporter.
+    File(PathBuf),
+}
+
+impl Target {
+    fn parse(target: &str) -> Target {
+        match target.parse() {
+            Ok(addr) => Target::Http(addr),
+            Err(_) => Target::File(PathBuf::from(target)),
+        }
+    }
+}
+
+/// Export metrics to `target`, which is an address like 127.0.0.1:9123
+/// to serve them over HTTP, or a file to write them to. Either way a
+/// thread is started, so call `signals::handle` first, so that it
+/// leaves signals alone.
+pub fn init(target: &str) -> Result<(), String> {
+    match Target::parse(target) {
+        Target::Http(addr) => {
+            let listener = TcpListener::bind(addr).map_err(|e| format!("{}: {}", addr, e))?;
+            start()?;
+            spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    handle_request(stream);
+                }
+            });
+        }
+        Target::File(path) => {
+            write_file(&path)?;
+            start()?;
+            spawn(move || loop {
+                sleep(WRITE_INTERVAL);
+                if let Err(e) = write_file(&path) {
+                    warn!("Could not write metrics: {}", e);
+                }
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Start counting.
+fn start() -> Result<(), String> {
+    METRICS
+        .set(Mutex::new(BTreeMap::new()))
+        .map_err(|_| "Metrics are already set up".to_string())
+}
+
+/// Write the metrics file one last time before we exit, if there is one.
+pub fn flush(target: &str) {
+    if let Target::File(path) = Target::parse(target) {
+        if let Err(e) = write_file(&path) {
+            warn!("Could not write metrics: {}", e);
+        }
+    }
+}
+
+/// Update the counters of a mailbox, if metrics are being exported.
+pub fn update<F: FnOnce(&mut Counters)>(account: &str, mailbox: &str, f: F) {
+    if let Some(metrics) = METRICS.get() {
+        let mut metrics = metrics.lock().unwrap();
+        f(metrics
+            .entry((account.to_string(), mailbox.to_string()))
+            .or_default());
+    }
+}
+
+/// Count an activity of a mailbox.
+pub fn record(account: &str, mailbox: &str, activity: &Activity) {
+    update(account, mailbox, |c| match activity {
+        Activity::SyncStart | Activity::Error { .. } => (),
+        Activity::SyncEnd { duration_ms, .. } => {
+            c.sync_passes += 1;
+            c.sync_seconds += *duration_ms as f64 / 1000.0;
+        }
+        Activity::Download { size, .. } => {
+            c.downloads += 1;
+            c.downloaded_bytes += *size as u64;
+        }
+        Activity::LocalDelete { .. } => c.local_deletes += 1,
+        Activity::ServerDelete { .. } => c.server_deletes += 1,
+        Activity::LocalFlags { .. } => c.local_flags += 1,
+        Activity::ServerFlags { .. } => c.server_flags += 1,
+        Activity::Append { .. } => c.appends += 1,
+    });
+}
+
+/// The current time in seconds since the epoch.
+pub fn now() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Write the metrics to a file in one go, so that the textfile collector
+/// never reads half of them.
+fn write_file(path: &PathBuf) -> Result<(), String> {
+    let mut tmp = path.clone().into_os_string();
+    tmp.push(".tmp");
+    fs::write(&tmp, render()).map_err(|e| format!("{}: {}", path.display(), e))?;
+    fs::rename(&tmp, path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Answer an HTTP request with the metrics.
+fn handle_request(mut stream: TcpStream) {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT)).ok();
+    let mut reader = match stream.try_clone() {
+        Ok(reader) => BufReader::new(reader),
+        Err(_) => return,
+    };
+    let mut request = String::new();
+    if reader.read_line(&mut request).is_err() {
+        return;
+    }
+    // Skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header).is_ok_and(|n| n > 2) {
+        header.clear();
+    }
+
+    let mut words = request.split_whitespace();
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", render()),
+        (Some("GET"), _) => ("404 Not Found", "Not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "Only GET is allowed\n".to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )
+    .ok();
+}
+
+/// A metric, with how to read it from the counters of a mailbox.
+struct Metric {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    value: fn(&Counters) -> Option<f64>,
+}
+
+const METRIC_LIST: &[Metric] = &[
+    Metric {
+        name: "runt_messages_downloaded_total",
+        kind: "counter",
+        help: "Messages downloaded from the server into the Maildir.",
+        value: |c| Some(c.downloads as f64),
+    },
+    Metric {
+        name: "runt_downloaded_bytes_total",
+        kind: "counter",
+        help: "Bytes of messages downloaded from the server.",
+        value: |c| Some(c.downloaded_bytes as f64),
+    },
+    Metric {
+        name: "runt_messages_appended_total",
+        kind: "counter",
+        help: "New messages in the Maildir uploaded to the server.",
+        value: |c| Some(c.appends as f64),
+    },
+    Metric {
+        name: "runt_local_deletes_total",
+        kind: "counter",
+        help: "Messages deleted from the Maildir because they were deleted on the server.",
+        value: |c| Some(c.local_deletes as f64),
+    },
+    Metric {
+        name: "runt_server_deletes_total",
+        kind: "counter",
+        help: "Messages deleted from the server because they were deleted from the Maildir.",
+        value: |c| Some(c.server_deletes as f64),
+    },
+    Metric {
+        name: "runt_local_flag_updates_total",
+        kind: "counter",
+        help: "Flag changes on the server copied to the Maildir.",
+        value: |c| Some(c.local_flags as f64),
+    },
+    Metric {
+        name: "runt_server_flag_updates_total",
+        kind: "counter",
+        help: "Flag changes in the Maildir copied to the server.",
+        value: |c| Some(c.server_flags as f64),
+    },
+    Metric {
+        name: "runt_connections_total",
+        kind: "counter",
+        help: "IMAP connections opened for sync passes and IDLE.",
+        value: |c| Some(c.connections as f64),
+    },
+    Metric {
+        name: "runt_idle_wakeups_total",
+        kind: "counter",
+        help: "Times IDLE woke up for changes on the server.",
+        value: |c| Some(c.idle_wakeups as f64),
+    },
+    Metric {
+        name: "runt_sync_failures_total",
+        kind: "counter",
+        help: "Sync passes that failed.",
+        value: |c| Some(c.sync_failures as f64),
+    },
+    Metric {
+        name: "runt_sync_failing",
+        kind: "gauge",
+        help: "1 if the last sync pass failed and it is waiting to be retried.",
+        value: |c| Some(if c.failing { 1.0 } else { 0.0 }),
+    },
+    Metric {
+        name: "runt_last_successful_sync_timestamp_seconds",
+        kind: "gauge",
+        help: "When the last sync pass without errors finished.",
+        value: |c| c.last_success,
+    },
+];
+
+/// The metrics of every mailbox, in the Prometheus text format.
+fn render() -> String {
+    let mut text = String::new();
+    let metrics = match METRICS.get() {
+        Some(metrics) => metrics.lock().unwrap(),
+        None => return text,
+    };
+    for metric in METRIC_LIST {
+        writeln!(text, "# HELP {} {}", metric.name, metric.help).ok();
+        writeln!(text, "# TYPE {} {}", metric.name, metric.kind).ok();
+        for ((account, mailbox), counters) in metrics.iter() {
+            if let Some(value) = (metric.value)(counters) {
+                sample(&mut text, metric.name, account, mailbox, value);
+            }
+        }
+    }
+    text.push_str("# HELP runt_sync_duration_seconds How long sync passes took.\n");
+    text.push_str("# TYPE runt_sync_duration_seconds summary\n");
+    for ((account, mailbox), counters) in metrics.iter() {
+        let (sum, count) = (counters.sync_seconds, counters.sync_passes as f64);
+        sample(
+            &mut text,
+            "runt_sync_duration_seconds_sum",
+            account,
+            mailbox,
+            sum,
+        );
+        sample(
+            &mut text,
+            "runt_sync_duration_seconds_count",
+            account,
+            mailbox,
+            count,
+        );
+    }
+    text
+}
+
+/// Add a sample of a mailbox to the metrics.
+fn sample(text: &mut String, name: &str, account: &str, mailbox: &str, value: f64) {
+    writeln!(
+        text,
+        "{}{{account=\"{}\",mailbox=\"{}\"}} {}",
+        name,
+        escape(account),
+        escape(mailbox),
+        value
+    )
+    .ok();
+}
+
+/// Escape a label value.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,75 @@
+use mailparse::{parse_headers, MailHeaderMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A message downloaded into a Maildir, for the new mail command.
+#[derive(Serialize)]
+pub struct NewMail {
+    pub account: String,
+    pub mailbox: String,
+    pub uid: u32,
+    pub id: String,
+    /// The message file, as it was saved.
+    pub path: PathBuf,
+    pub from: Option<String>,
+    pub subject: Option<String>,
+}
+
+impl NewMail {
+    /// Fill in From and Subject from the headers of the message.
+    pub fn with_headers(mut self, body: &[u8]) -> NewMail {
+        if let Ok((headers, _)) = parse_headers(body) {
+            // Values of CRLF headers keep the CR
+            let value = |name| {
+                headers
+                    .get_first_value(name)
+                    .map(|value| value.trim().to_string())
+            };
+            self.from = value("From");
+            self.subject = value("Subject");
+        }
+        self
+    }
+}
+
+/// Run `command` with the shell for messages downloaded into the Maildir
+/// at `maildir` in a sync pass. Each message is written to its standard
+/// input as a line of JSON, and the environment tells it where they are
+/// and how many there are.
+pub fn run(command: &str, maildir: &Path, mail: &[NewMail]) -> Result<(), String> {
+    let first = match mail.first() {
+        Some(first) => first,
+        None => return Ok(()),
+    };
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("RUNT_ACCOUNT", &first.account)
+        .env("RUNT_MAILBOX", &first.mailbox)
+        .env("RUNT_MAILDIR", maildir)
+        .env("RUNT_NEW_MAIL", mail.len().to_string())
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not execute {:?}: {}", command, e))?;
+
+    let mut lines = String::new();
+    for message in mail {
+        if let Ok(line) = serde_json::to_string(message) {
+            lines.push_str(&line);
+            lines.push('\n');
+        }
+    }
+    // The command does not have to read its input
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(lines.as_bytes()).ok();
+    }
+
+    let status = child
+        .wait()
+        .map_err(|e| format!("{:?} failed: {}", command, e))?;
+    if !status.success() {
+        return Err(format!("{:?} failed: {}", command, status));
+    }
+    Ok(())
+}
@@ -0,0 +1,806 @@
+use crate::cli::Options;
+use crate::config::{Account, Config, MailboxSettings};
+use crate::control::Request;
+use crate::imapw::{Imap, Trace, IDLE_KEEPALIVE};
+use crate::syncdir::{Status, SyncDir, SyncMessage};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::Duration;
+
+/// How long a mailbox may go without showing signs of life while it is
+/// syncing or IDLEing before we think it is stuck. IDLE shows signs of
+/// life every `IDLE_KEEPALIVE`, so this allows for one slow keepalive.
+const STUCK_TIMEOUT: Duration = IDLE_KEEPALIVE.saturating_mul(2);
+
+/// How long listing the mailboxes of an account may take, so that a
+/// server that does not answer cannot hold up a reload forever.
+const LIST_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Requests for the runner, from signals and the control socket.
+pub enum Event {
+    /// Stop everything and exit.
+    Shutdown,
+    /// Re-read the config.
+    Reload,
+    /// The config has been re-read for a reload, and what it asks for.
+    Reloaded(Result<Plan, String>),
+    /// The mailboxes stopped by a reload have finished.
+    Stopped,
+    /// A mailbox that is not IDLEd has finished its sync pass.
+    Finished(String, String),
+    /// Synchronize every mailbox now.
+    Sync,
+    /// Log the status of every mailbox.
+    Status,
+    /// Pause every account.
+    Pause,
+    /// Resume every paused account.
+    Resume,
+    /// A request from the control socket, and where to send the reply.
+    Request(Request, Sender<Result<String, String>>),
+}
+
+/// A mailbox that has been started, and the settings it was started with.
+struct Running {
+    account: Account,
+    settings: MailboxSettings,
+    sender: Sender<SyncMessage>,
+    status: SharedStatus,
+    /// Gets whether the sync succeeded when it finishes.
+    done: Receiver<bool>,
+    /// The mailbox is IDLEd, so it keeps running after its first pass.
+    idle: bool,
+    /// Start the mailbox again once it finishes, because a sync was
+    /// asked for during its single pass.
+    again: bool,
+}
+
+impl Running {
+    /// Has the mailbox finished, whether it succeeded or not?
+    fn has_finished(&self) -> bool {
+        !matches!(self.done.try_recv(), Err(TryRecvError::Empty))
+    }
+}
+
+/// The mailboxes a config asks for, found by listing the mailboxes of
+/// every account.
+pub struct Plan {
+    config: Config,
+    wanted: HashMap<(String, String), (Account, MailboxSettings)>,
+    /// Accounts that were paused or could not be listed, whose
+    /// mailboxes are left as they are.
+    unlisted: HashSet<String>,
+    failed: usize,
+}
+
+impl Plan {
+    /// List the mailboxes of the selected accounts, except for the paused
+    /// ones, since listing their mailboxes means connecting to the server.
+    /// It is an error if a mailbox given with --mailbox is on none of the
+    /// servers.
+    fn new(opts: &Options, paused: &HashSet<String>, config: Config) -> Result<Plan, String> {
+        let mut wanted = HashMap::new();
+        let mut unlisted = HashSet::new();
+        let mut found = HashSet::new();
+        let mut failed = 0;
+        for account in &config.accounts {
+            if !opts.is_account_selected(&account.account) {
+                continue;
+            }
+            if paused.contains(&account.account) {
+                info!("Not reloading paused account {}", account.account);
+                unlisted.insert(account.account.clone());
+                continue;
+            }
+            match list_mailboxes(opts, account) {
+                Ok(names) => {
+                    for name in names {
+                        if opts.mailboxes.contains(&name) {
+                            found.insert(name.clone());
+                        }
+                        let settings = account.mailbox_settings(&name);
+                        if settings.excluded || !opts.is_mailbox_selected(&name) {
+                            continue;
+                        }
+                        wanted.insert((account.account.clone(), name), (account.clone(), settings));
+                    }
+                }
+                Err(e) => {
+                    error!(
+                        "Could not list mailboxes of account {}: {}",
+                        account.account, e
+                    );
+                    unlisted.insert(account.account.clone());
+                    failed += 1;
+                }
+            }
+        }
+
+        // A mailbox could be on a server we could not list
+        if unlisted.is_empty() {
+            let missing: Vec<String> = opts
+                .mailboxes
+                .iter()
+                .filter(|name| !found.contains(*name))
+                .map(|name| format!("No mailbox named {:?} on the server", name))
+                .collect();
+            if !missing.is_empty() {
+                return Err(missing.join("; "));
+            }
+        }
+
+        Ok(Plan {
+            config,
+            wanted,
+            unlisted,
+            failed,
+        })
+    }
+}
+
+/// Connect to the account and get the names of the mailboxes that
+/// can be selected.
+fn list_mailboxes(opts: &Options, config: &Account) -> Result<Vec<String>, String> {
+    let trace = if opts.trace_imap || config.trace_imap.unwrap_or(false) {
+        Some(Trace::open(&config.trace_path(None), "list")?)
+    } else {
+        None
+    };
+    let mut imap = Imap::with_timeout(config, trace, Some(LIST_TIMEOUT))?;
+    // A connection that failed is not worth logging out of
+    let listing = imap.list(None, Some("*"))?;
+    imap.logout().ok();
+    Ok(listing
+        .iter()
+        .filter(|mailbox| {
+            !mailbox
+                .attributes()
+                .contains(&imap::types::NameAttribute::NoSelect)
+        })
+        .map(|mailbox| mailbox.name().to_string())
+        .collect())
+}
+
+/// A reload that is under way, and who to tell when it is done.
+struct Reloading {
+    reply: Option<Sender<Result<String, String>>>,
+    /// What to start once the mailboxes it replaces have stopped.
+    plan: Option<Plan>,
+    /// The thread waiting for those mailboxes to stop.
+    waiter: Option<JoinHandle<()>>,
+}
+
+/// The status of a mailbox, which its SyncDir keeps up to date.
+type SharedStatus = Arc<Mutex<Status>>;
+
+/// The status of every running mailbox, shared with the systemd
+/// notifier, which looks at it from a thread of its own.
+#[derive(Clone, Default)]
+pub struct Statuses(Arc<Mutex<HashMap<(String, String), SharedStatus>>>);
+
+impl Statuses {
+    /// Whether every mailbox has finished its first sync pass, or
+    /// was paused before it could.
+    pub fn is_started(&self) -> bool {
+        self.0.lock().unwrap().values().all(|status| {
+            let status = status.lock().unwrap();
+            status.last_sync.is_some() || status.errors > 0 || status.paused
+        })
+    }
+
+    /// The mailboxes that seem to be stuck, as account/mailbox.
+    pub fn stuck(&self) -> Vec<String> {
+        let mut stuck: Vec<String> = self
+            .0
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(_, status)| status.lock().unwrap().is_stuck(STUCK_TIMEOUT))
+            .map(|((account, mailbox), _)| format!("{}/{}", account, mailbox))
+            .collect();
+        stuck.sort();
+        stuck
+    }
+
+    /// A one line summary of how the mailboxes are doing.
+    pub fn summary(&self) -> String {
+        let (mut in_sync, mut syncing, mut failing, mut paused) = (0, 0, 0, 0);
+        let statuses = self.0.lock().unwrap();
+        for status in statuses.values() {
+            let status = status.lock().unwrap();
+            if status.paused {
+                paused += 1;
+            } else if status.syncing {
+                syncing += 1;
+            } else if status.failing {
+                failing += 1;
+            } else if status.last_sync.is_some() {
+                in_sync += 1;
+            }
+        }
+        let mut summary = format!(
+            "{} mailbox(es): {} in sync, {} syncing, {} failing, {} paused",
+            statuses.len(),
+            in_sync,
+            syncing,
+            failing,
+            paused
+        );
+        drop(statuses);
+        let stuck = self.stuck();
+        if !stuck.is_empty() {
+            summary.push_str(&format!(", stuck: {}", stuck.join(", ")));
+        }
+        summary
+    }
+}
+
+/// Starts a SyncDir for every selected mailbox in the config, and keeps
+/// track of them so they can be stopped, or brought in line with a new
+/// config.
+pub struct Runner {
+    opts: Options,
+    path: PathBuf,
+    running: HashMap<(String, String), Running>,
+    /// The accounts that are paused.
+    paused: HashSet<String>,
+    failed: usize,
+    /// Where reloads send their events, so they don't block the event loop.
+    events: Sender<Event>,
+    reloading: Option<Reloading>,
+    /// The status of every mailbox in `running`.
+    statuses: Statuses,
+}
+
+impl Runner {
+    pub fn new(opts: &Options, path: &Path, events: Sender<Event>) -> Runner {
+        Runner {
+            opts: opts.clone(),
+            path: path.to_path_buf(),
+            running: HashMap::new(),
+            paused: HashSet::new(),
+            failed: 0,
+            events,
+            reloading: None,
+            statuses: Statuses::default(),
+        }
+    }
+
+    /// The status of every running mailbox, kept up to date as mailboxes
+    /// are started and stopped.
+    pub fn statuses(&self) -> Statuses {
+        self.statuses.clone()
+    }
+
+    /// Bring `statuses` in line with `running`.
+    fn update_statuses(&self) {
+        *self.statuses.0.lock().unwrap() = self
+            .running
+            .iter()
+            .map(|(key, running)| (key.clone(), running.status.clone()))
+            .collect();
+    }
+
+    /// Start re-reading the config file. That and listing the mailboxes
+    /// happen in a thread of their own, which sends `Reloaded` when it
+    /// is done. If the config has errors, then we keep running with the
+    /// old one. The reply, if any, is sent when the reload is over.
+    pub fn reload(&mut self, reply: Option<Sender<Result<String, String>>>) {
+        if self.reloading.is_some() {
+            warn!("Not reloading the config, since a reload is under way");
+            if let Some(reply) = reply {
+                reply.send(Err("A reload is under way".to_string())).ok();
+            }
+            return;
+        }
+        info!("Reloading config from {}", self.path.display());
+        self.reloading = Some(Reloading {
+            reply,
+            plan: None,
+            waiter: None,
+        });
+        let opts = self.opts.clone();
+        let path = self.path.clone();
+        let paused = self.paused.clone();
+        let events = self.events.clone();
+        spawn(move || {
+            let plan = Config::new(&path)
+                .map_err(|errors| {
+                    errors
+                        .iter()
+                        .map(|e| e.to_string())
+                        .collect::<Vec<String>>()
+                        .join("; ")
+                })
+                .and_then(|config| Plan::new(&opts, &paused, config));
+            events.send(Event::Reloaded(plan)).ok();
+        });
+    }
+
+    /// Carry on with a reload once the config has been read. Mailboxes
+    /// that have to stop are told to, and a thread waits for them and
+    /// sends `Stopped`, so that the event loop is not held up by a
+    /// mailbox that is in the middle of a sync pass.
+    pub fn reloaded(&mut self, plan: Result<Plan, String>) {
+        let mut plan = match plan {
+            Ok(plan) => plan,
+            Err(e) => {
+                error!("{}", e);
+                error!("Config not reloaded");
+                self.finish_reload(Err(format!("Config not reloaded: {}", e)));
+                return;
+            }
+        };
+        self.failed += plan.failed;
+        let done = self.stop_stale(&mut plan);
+        let events = self.events.clone();
+        let waiter = spawn(move || {
+            for done in done {
+                done.recv().ok();
+            }
+            events.send(Event::Stopped).ok();
+        });
+        if let Some(reloading) = self.reloading.as_mut() {
+            reloading.plan = Some(plan);
+            reloading.waiter = Some(waiter);
+        }
+    }
+
+    /// Finish a reload once the mailboxes it stopped have finished, by
+    /// starting the new ones.
+    pub fn stopped(&mut self) {
+        if let Some(plan) = self.reloading.as_mut().and_then(|r| r.plan.take()) {
+            self.start_planned(&plan);
+        }
+        self.finish_reload(Ok(String::new()));
+    }
+
+    fn finish_reload(&mut self, result: Result<String, String>) {
+        if let Some(reply) = self.reloading.take().and_then(|r| r.reply) {
+            reply.send(result).ok();
+        }
+    }
+
+    /// Carry out a request from the control socket, and send the reply.
+    pub fn handle(&mut self, request: Request, reply: Sender<Result<String, String>>) {
+        let result = match request {
+            Request::Sync(account, mailbox) => self
+                .sync(account.as_deref(), mailbox.as_deref())
+                .map(|n| format!("Synchronizing {} mailbox(es)", n)),
+            Request::Status => Ok(self.status()),
+            Request::Reload => return self.reload(Some(reply)),
+            Request::Pause(account) => self
+                .pause(account.as_deref())
+                .map(|n| format!("Paused {} account(s)", n)),
+            Request::Resume(account) => self
+                .resume(account.as_deref())
+                .map(|n| format!("Resumed {} account(s)", n)),
+        };
+        reply.send(result).ok();
+    }
+
+    /// Bring the running mailboxes in line with the config, waiting for
+    /// the ones that have to stop. If a mailbox given with --mailbox is
+    /// on none of the servers, then nothing is changed.
+    pub fn apply(&mut self, config: &Config) -> Result<(), String> {
+        let mut plan = Plan::new(&self.opts, &self.paused, config.clone())?;
+        self.failed += plan.failed;
+        for done in self.stop_stale(&mut plan) {
+            done.recv().ok();
+        }
+        self.start_planned(&plan);
+        Ok(())
+    }
+
+    /// Tell the mailboxes that are no longer wanted, or whose settings
+    /// changed, to stop. The rest are left alone, so they keep their IDLE
+    /// connections, and so are paused accounts. Returns where each of the
+    /// stopped mailboxes says when it has finished.
+    fn stop_stale(&mut self, plan: &mut Plan) -> Vec<Receiver<bool>> {
+        self.paused
+            .retain(|paused| plan.config.accounts.iter().any(|a| &a.account == paused));
+        // An account may have been paused while the plan was made
+        plan.unlisted.extend(self.paused.iter().cloned());
+        let unlisted = &plan.unlisted;
+        plan.wanted
+            .retain(|(account, _), _| !unlisted.contains(account));
+
+        // If we could not get the mailboxes of an account, then we
+        // don't know what changed, so leave it as it is.
+        let wanted = &plan.wanted;
+        let stale: Vec<(String, String)> = self
+            .running
+            .iter()
+            .filter(|((account, _), _)| !unlisted.contains(account))
+            .filter(|(key, running)| match wanted.get(*key) {
+                Some((account, settings)) => {
+                    !running.account.same_account_settings(account) || running.settings != *settings
+                }
+                None => true,
+            })
+            .map(|(key, _)| key.clone())
+            .collect();
+        let mut done = Vec::new();
+        for key in stale {
+            if wanted.contains_key(&key) {
+                info!("Restarting {}/{} with new settings", key.0, key.1);
+            } else {
+                info!("Stopping {}/{}", key.0, key.1);
+            }
+            if let Some(running) = self.running.remove(&key) {
+                running.sender.send(SyncMessage::Exit).ok();
+                done.push(running.done);
+            }
+        }
+        self.update_statuses();
+        done
+    }
+
+    /// Start the mailboxes in the plan that are not running.
+    fn start_planned(&mut self, plan: &Plan) {
+        for account in &plan.config.accounts {
+            let names: Vec<String> = plan
+                .wanted
+                .keys()
+                .filter(|key| key.0 == account.account && !self.running.contains_key(*key))
+                .map(|key| key.1.clone())
+                .collect();
+            if !names.is_empty() {
+                self.start(account, names, HashMap::new());
+            }
+        }
+    }
+
+    /// Start synchronizing the given mailboxes of an account. Mailboxes
+    /// that are started again carry on with their old status.
+    fn start(
+        &mut self,
+        config: &Account,
+        names: Vec<String>,
+        mut statuses: HashMap<String, SharedStatus>,
+    ) {
+        let mut idle_mailboxes = Vec::new();
+        let mut pool_mailboxes = Vec::new();
+        for name in names {
+            match SyncDir::new(config, name.clone(), &self.opts) {
+                Err(e) => {
+                    error!(
+                        "Could not set up mailbox {} of account {}: {}",
+                        name, config.account, e
+                    );
+                    self.failed += 1;
+                }
+                Ok(mut sd) => {
+                    if let Some(status) = statuses.remove(&name) {
+                        sd.status = status;
+                    }
+                    let (done_tx, done) = channel();
+                    let idle = sd.should_idle();
+                    self.running.insert(
+                        (config.account.clone(), name.clone()),
+                        Running {
+                            account: config.clone(),
+                            settings: config.mailbox_settings(&name),
+                            sender: sd.sender.clone(),
+                            status: sd.status.clone(),
+                            done,
+                            idle,
+                            again: false,
+                        },
+                    );
+                    if idle {
+                        idle_mailboxes.push((sd, done_tx));
+                    } else {
+                        pool_mailboxes.push((sd, done_tx));
+                    }
+                }
+            }
+        }
+        self.update_statuses();
+
+        // Handle if the user has specified some maximum number of threads
+        // to run with. We have to allocate one thread for every idle
+        // mailbox, and remaining threads do all of the sync-once mailboxes.
+        let mut pool_size = pool_mailboxes.len();
+        if let Some(max_threads) = config.max_concurrency {
+            if let Some(pool) = max_threads.checked_sub(idle_mailboxes.len()) {
+                pool_size = pool;
+            } else {
+                pool_size = 0;
+            }
+
+            if pool_size == 0 && !pool_mailboxes.is_empty() {
+                warn!("Account {}.max_concurrency ({}) is too small for the number of idle mailboxes ({}) and non-idle mailboxes.", config.account, max_threads, idle_mailboxes.len(), );
+                warn!("You may see errors from the server and some mailboxes may not be synchronized.\nTo fix this, specify a number of mailboxes to idle that is smaller that max_concurrency, or increase max_concurrency if possible.");
+                pool_size = 1;
+            }
+        }
+
+        idle_mailboxes.into_iter().for_each(|(mut sd, done)| {
+            spawn(move || {
+                if let Err(what) = sd.sync() {
+                    error!("Error in sync thread: {}", what);
+                    done.send(false).ok();
+                } else {
+                    done.send(true).ok();
+                }
+            });
+        });
+
+        if !pool_mailboxes.is_empty() {
+            if let Ok(pool) = rayon::ThreadPoolBuilder::new()
+                .num_threads(pool_size)
+                .build()
+            {
+                pool_mailboxes.into_iter().for_each(|(mut sd, done)| {
+                    let events = self.events.clone();
+                    let account = config.account.clone();
+                    pool.spawn(move || {
+                        let res = sd.sync();
+                        if let Err(e) = &res {
+                            error!("Synchronize-once for mailbox {} failed: {}", sd.mailbox, e);
+                        }
+                        done.send(res.is_ok()).ok();
+                        events.send(Event::Finished(account, sd.mailbox)).ok();
+                    })
+                });
+            }
+        }
+    }
+
+    /// Ask every mailbox to synchronize now, instead of waiting for
+    /// a change.
+    pub fn sync_all(&mut self) {
+        info!("Synchronizing all mailboxes");
+        self.sync(None, None).ok();
+    }
+
+    /// Ask the mailboxes of an account, or a single mailbox, to
+    /// synchronize now. Mailboxes that are not IDLEd only make a single
+    /// sync pass, so they are started again for another one.
+    /// Returns how many mailboxes were asked or started.
+    fn sync(&mut self, account: Option<&str>, mailbox: Option<&str>) -> Result<usize, String> {
+        let matching: Vec<(String, String)> = self
+            .running
+            .keys()
+            .filter(|(a, m)| {
+                account.is_none_or(|account| account == a)
+                    && mailbox.is_none_or(|mailbox| mailbox == m)
+            })
+            .cloned()
+            .collect();
+        if matching.is_empty() {
+            return Err(match (account, mailbox) {
+                (Some(a), Some(m)) => format!("Mailbox {}/{} is not being synchronized", a, m),
+                (Some(a), None) => format!("Account {} is not being synchronized", a),
+                _ => "Nothing is being synchronized".to_string(),
+            });
+        }
+        let unpaused: Vec<(String, String)> = matching
+            .into_iter()
+            .filter(|(a, _)| !self.paused.contains(a))
+            .collect();
+        if unpaused.is_empty() {
+            return Err(match account {
+                Some(a) => format!("Account {} is paused", a),
+                None => "Every account is paused".to_string(),
+            });
+        }
+        match self.send_or_restart(unpaused, || SyncMessage::Sync) {
+            0 => Err("Could not start synchronizing, see the log".to_string()),
+            count => Ok(count),
+        }
+    }
+
+    /// Send a message to the given mailboxes. Mailboxes that are not
+    /// IDLEd never read it, so they are started again instead, right away
+    /// if they have finished, or else once they finish. Mailboxes that
+    /// have failed are started again too. Returns how many mailboxes got
+    /// the message or will start again.
+    fn send_or_restart(
+        &mut self,
+        keys: Vec<(String, String)>,
+        message: fn() -> SyncMessage,
+    ) -> usize {
+        let mut count = 0;
+        let mut finished = Vec::new();
+        for key in keys {
+            let running = match self.running.get_mut(&key) {
+                Some(running) => running,
+                None => continue,
+            };
+            // The send fails if the mailbox has finished
+            if running.idle && running.sender.send(message()).is_ok() {
+                count += 1;
+            } else if running.idle || running.has_finished() {
+                finished.push(key);
+            } else {
+                running.again = true;
+                count += 1;
+            }
+        }
+        count + self.restart(finished)
+    }
+
+    /// A mailbox that is not IDLEd has finished its sync pass. If another
+    /// sync was asked for meanwhile, start it again.
+    pub fn finished(&mut self, account: String, mailbox: String) {
+        let key = (account, mailbox);
+        let again = match self.running.get(&key) {
+            // This could be about an earlier run of the mailbox
+            Some(running) => running.again && running.has_finished(),
+            None => false,
+        };
+        if again && !self.paused.contains(&key.0) {
+            self.restart(vec![key]);
+        }
+    }
+
+    /// Start mailboxes that have finished again, all the mailboxes of an
+    /// account together, so that they share its threads. Returns how many
+    /// started.
+    fn restart(&mut self, keys: Vec<(String, String)>) -> usize {
+        let mut accounts: HashMap<String, (Account, Vec<String>, HashMap<_, _>)> = HashMap::new();
+        for key in &keys {
+            if let Some(running) = self.running.remove(key) {
+                info!("Starting {}/{} again", key.0, key.1);
+                let (_, names, statuses) = accounts
+                    .entry(key.0.clone())
+                    .or_insert_with(|| (running.account.clone(), Vec::new(), HashMap::new()));
+                names.push(key.1.clone());
+                statuses.insert(key.1.clone(), running.status);
+            }
+        }
+        for (_, (account, names, statuses)) in accounts {
+            self.start(&account, names, statuses);
+        }
+        keys.iter()
+            .filter(|key| self.running.contains_key(*key))
+            .count()
+    }
+
+    /// Pause every account.
+    pub fn pause_all(&mut self) {
+        info!("Pausing all accounts");
+        self.pause(None).ok();
+    }
+
+    /// Resume every paused account.
+    pub fn resume_all(&mut self) {
+        if !self.paused.is_empty() {
+            info!("Resuming all accounts");
+            self.resume(None).ok();
+        }
+    }
+
+    /// The running accounts, or the given one if it is running.
+    fn accounts(&self, account: Option<&str>) -> Result<HashSet<String>, String> {
+        let accounts: HashSet<String> = self
+            .running
+            .keys()
+            .filter(|(a, _)| account.is_none_or(|account| account == a))
+            .map(|(a, _)| a.clone())
+            .collect();
+        match account {
+            Some(a) if accounts.is_empty() => {
+                Err(format!("Account {} is not being synchronized", a))
+            }
+            _ => Ok(accounts),
+        }
+    }
+
+    /// Send a message to every mailbox of the given accounts.
+    fn send_to_accounts(&self, accounts: &HashSet<String>, message: fn() -> SyncMessage) {
+        for ((a, _), running) in &self.running {
+            if accounts.contains(a) {
+                running.sender.send(message()).ok();
+            }
+        }
+    }
+
+    /// Stop synchronizing an account, or every account, until it is
+    /// resumed. Its IDLE connections are closed, and changes to the
+    /// Maildir are ignored meanwhile. Returns how many accounts were
+    /// paused.
+    fn pause(&mut self, account: Option<&str>) -> Result<usize, String> {
+        let accounts: HashSet<String> = self
+            .accounts(account)?
+            .into_iter()
+            .filter(|a| !self.paused.contains(a))
+            .collect();
+        self.send_to_accounts(&accounts, || SyncMessage::Pause);
+        self.paused.extend(accounts.iter().cloned());
+        Ok(accounts.len())
+    }
+
+    /// Resume a paused account, or every paused account, with a single
+    /// sync pass to catch up with what happened meanwhile. Mailboxes that
+    /// are not IDLEd are started again for that pass. Returns how many
+    /// accounts were resumed.
+    fn resume(&mut self, account: Option<&str>) -> Result<usize, String> {
+        let accounts: HashSet<String> = self
+            .accounts(account)?
+            .into_iter()
+            .filter(|a| self.paused.contains(a))
+            .collect();
+        self.paused.retain(|a| !accounts.contains(a));
+        let keys: Vec<(String, String)> = self
+            .running
+            .keys()
+            .filter(|(a, _)| accounts.contains(a))
+            .cloned()
+            .collect();
+        self.send_or_restart(keys, || SyncMessage::Resume);
+        Ok(accounts.len())
+    }
+
+    /// Log the status of every mailbox.
+    pub fn log_status(&self) {
+        for line in self.status().lines() {
+            info!("{}", line);
+        }
+    }
+
+    /// The status of every mailbox, one per line.
+    fn status(&self) -> String {
+        let mut keys: Vec<&(String, String)> = self.running.keys().collect();
+        keys.sort();
+        let mut lines = vec![format!("Status of {} mailbox(es):", keys.len())];
+        for key in keys {
+            let status = self.running[key].status.lock().unwrap().clone();
+            let last_sync = match status.last_sync {
+                Some(time) => time.format("%Y-%m-%d %H:%M:%S").to_string(),
+                None => "never".to_string(),
+            };
+            lines.push(format!(
+                "  {}/{}: last sync {}, {} error(s), {}{}",
+                key.0,
+                key.1,
+                last_sync,
+                status.errors,
+                if status.paused {
+                    "paused"
+                } else if status.idling {
+                    "idling"
+                } else {
+                    "not idling"
+                },
+                match status.last_error {
+                    Some(e) => format!(", last error: {}", e),
+                    None => String::new(),
+                }
+            ));
+        }
+        lines.join("\n")
+    }
+
+    /// Stop every mailbox and wait for them all to finish, including
+    /// the ones a reload is stopping.
+    pub fn stop_all(&mut self) {
+        for running in self.running.values() {
+            running.sender.send(SyncMessage::Exit).ok();
+        }
+        for (_, running) in self.running.drain() {
+            running.done.recv().ok();
+        }
+        self.update_statuses();
+        if let Some(waiter) = self.reloading.take().and_then(|r| r.waiter) {
+            waiter.join().ok();
+        }
+    }
+
+    /// Wait for every mailbox to finish on its own, which they do when
+    /// only syncing once. Returns the number of accounts and mailboxes
+    /// that failed.
+    pub fn wait(&mut self) -> usize {
+        let failed = self
+            .running
+            .drain()
+            .filter(|(_, running)| !running.done.recv().unwrap_or(false))
+            .count();
+        self.failed + failed
+    }
+}
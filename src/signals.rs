@@ -0,0 +1,62 @@
+use crate::runner::Event;
+use libc::{c_int, sigset_t, SIGCONT, SIGHUP, SIGINT, SIGTERM, SIGTSTP, SIGUSR1, SIGUSR2};
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::mpsc::Sender;
+use std::thread::spawn;
+
+/// The signals we handle, and what they ask us to do.
+fn event_for(signal: c_int) -> Option<Event> {
+    match signal {
+        SIGINT | SIGTERM => Some(Event::Shutdown),
+        SIGHUP => Some(Event::Reload),
+        SIGUSR1 => Some(Event::Sync),
+        SIGUSR2 => Some(Event::Status),
+        SIGTSTP => Some(Event::Pause),
+        SIGCONT => Some(Event::Resume),
+        _ => None,
+    }
+}
+
+/// Block our signals in this thread, and so in every thread started from
+/// it later, and start a thread that waits for them and sends the events
+/// they stand for. This must be called before any other threads start.
+///
+/// A second SIGINT or SIGTERM exits straight away, in case shutting
+/// down gracefully hangs.
+pub fn handle(events: Sender<Event>) {
+    let set = unsafe {
+        let mut set = MaybeUninit::<sigset_t>::uninit();
+        libc::sigemptyset(set.as_mut_ptr());
+        let mut set = set.assume_init();
+        for signal in &[SIGINT, SIGTERM, SIGHUP, SIGUSR1, SIGUSR2, SIGTSTP, SIGCONT] {
+            libc::sigaddset(&mut set, *signal);
+        }
+        libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+        set
+    };
+
+    spawn(move || {
+        let mut shutting_down = false;
+        loop {
+            let mut signal: c_int = 0;
+            if unsafe { libc::sigwait(&set, &mut signal) } != 0 {
+                continue;
+            }
+            let event = match event_for(signal) {
+                Some(event) => event,
+                None => continue,
+            };
+            if let Event::Shutdown = event {
+                if shutting_down {
+                    warn!("Exiting without waiting for synchronization to stop");
+                    std::process::exit(1);
+                }
+                shutting_down = true;
+            }
+            if events.send(event).is_err() {
+                break;
+            }
+        }
+    });
+}
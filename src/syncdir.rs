@@ -1,29 +1,87 @@
+use crate::activity::{self, Activity};
 use crate::cache::maildir_flags_from_imap;
 use crate::cache::Cache;
 use crate::cache::MessageMeta;
 use crate::cache::SyncFlags;
-use crate::config::Account;
-use crate::imapw::{FetchResult, Imap, UidResult};
+use crate::cli::Options;
+use crate::config::{Account, Direction, MailboxSettings};
+use crate::imapw::{FetchResult, Imap, Interrupter, Trace, UidResult};
 use crate::maildirw::Maildir;
+use crate::metrics::{self, Counters};
+use crate::newmail::{self, NewMail};
 use chrono::prelude::*;
 use imap::types::{Fetch, Mailbox, Uid, UnsolicitedResponse, ZeroCopy};
-use notify::{watcher, RecursiveMode, Watcher};
+use log::Level;
+use notify::{watcher, RecommendedWatcher, RecursiveMode, Watcher};
 use std::collections::HashSet;
 use std::fs;
 use std::ops::Deref;
-use std::sync::mpsc::{channel, Receiver, RecvError, Sender, TryRecvError};
-use std::thread::{sleep, spawn, JoinHandle};
-use std::time::Duration;
+use std::sync::mpsc::{channel, Receiver, RecvError, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{spawn, JoinHandle};
+use std::time::{Duration, Instant};
 use std::vec::Vec;
 
 /// A enum used to pass messages between threads.
 #[derive(Debug)]
 pub enum SyncMessage {
     Exit,
+    Sync,
     ImapChanged,
     ImapError(String),
     MaildirChanged,
     MaildirError(String),
+    Pause,
+    Resume,
+}
+
+/// How a mailbox is doing, for status reports.
+#[derive(Clone, Debug, Default)]
+pub struct Status {
+    /// When the last sync pass finished without errors.
+    pub last_sync: Option<DateTime<Local>>,
+    /// How many sync passes have failed.
+    pub errors: usize,
+    /// The error from the last failed sync pass.
+    pub last_error: Option<String>,
+    /// The last sync pass failed.
+    pub failing: bool,
+    /// A sync pass is under way.
+    pub syncing: bool,
+    /// We are waiting for changes on the server with IDLE.
+    pub idling: bool,
+    /// Synchronization is paused until resumed.
+    pub paused: bool,
+    /// When we last showed signs of life while syncing or IDLEing.
+    /// If this gets old, then we are probably stuck.
+    pub heartbeat: Option<Instant>,
+}
+
+impl Status {
+    /// Note that we are still alive.
+    fn beat(&mut self) {
+        self.heartbeat = Some(Instant::now());
+    }
+
+    /// Whether we have been syncing or IDLEing for longer than `timeout`
+    /// without showing signs of life.
+    pub fn is_stuck(&self, timeout: Duration) -> bool {
+        (self.syncing || self.idling)
+            && !self.paused
+            && self.heartbeat.is_some_and(|beat| beat.elapsed() > timeout)
+    }
+}
+
+/// The changes a dry run would have made, so we can summarize them at
+/// the end of the sync pass.
+#[derive(Default)]
+struct DryRun {
+    downloads: HashSet<Uid>,
+    local_deletes: usize,
+    local_flags: usize,
+    server_deletes: usize,
+    server_flags: usize,
+    appends: usize,
 }
 
 /// A struct representing a single mailbox to synchronize
@@ -31,64 +89,126 @@ pub enum SyncMessage {
 pub struct SyncDir {
     pub config: Account,
     pub mailbox: String,
+    opts: Options,
+    settings: MailboxSettings,
     pub sender: Sender<SyncMessage>,
     receiver: Receiver<SyncMessage>,
+    pub status: Arc<Mutex<Status>>,
     cache: Cache,
     maildir: Maildir,
     idlethread: Option<JoinHandle<()>>,
+    idle_interrupter: Option<Interrupter>,
     fsthread: Option<JoinHandle<()>>,
+    fswatcher: Option<RecommendedWatcher>,
+    skipped: HashSet<Uid>,
+    /// When the sync pass under way started.
+    pass_started: Option<Instant>,
+    /// Messages downloaded in the sync pass under way, for the new mail
+    /// command.
+    new_mail: Vec<NewMail>,
+    dry_run: DryRun,
 }
 
 impl SyncDir {
     /// Make a new SyncDir from the given config and mailbox name
-    pub fn new(config: &Account, mailbox: String) -> Result<SyncDir, String> {
+    pub fn new(config: &Account, mailbox: String, opts: &Options) -> Result<SyncDir, String> {
         let myconfig = config.clone();
-        let cache = Cache::new(&myconfig.account, &mailbox).unwrap();
-        let maildir = Maildir::new(&myconfig.maildir, &myconfig.account, &mailbox)?;
+        let settings = config.mailbox_settings(&mailbox);
+        // A dry run changes nothing, not even by creating the cache
+        // or the Maildir
+        let (cache, maildir) = if opts.dry_run {
+            (
+                Cache::read_only(&config.cache_path(&mailbox))?,
+                Maildir::existing(&settings.maildir),
+            )
+        } else {
+            (
+                Cache::new(&config.cache_path(&mailbox))?,
+                Maildir::new(&settings.maildir)?,
+            )
+        };
         let (sender, receiver) = channel();
         Ok(SyncDir {
             config: myconfig,
+            settings,
             mailbox,
+            opts: opts.clone(),
             sender,
             receiver,
+            status: Arc::new(Mutex::new(Status::default())),
             cache,
             maildir,
             idlethread: None,
+            idle_interrupter: None,
             fsthread: None,
+            fswatcher: None,
+            skipped: HashSet::new(),
+            pass_started: None,
+            new_mail: Vec::new(),
+            dry_run: DryRun::default(),
         })
     }
 
-    /// Log a message to the console
+    /// Log a message about this mailbox at the given level
+    fn log_at(&self, level: Level, msg: &str) {
+        log!(level, "{}/{}: {}", self.config.account, self.mailbox, msg);
+    }
+
+    /// Log a message
     fn log(&self, msg: &str) {
-        println!(
-            "{} {}/{}: {}",
-            Local::now().format("%Y-%m-%d %H:%M:%S"),
-            self.config.account,
-            self.mailbox,
-            msg
-        );
+        self.log_at(Level::Info, msg);
     }
 
-    /// Log an error message to the console
+    /// Log an error message, and add it to the activity stream
     fn elog(&self, msg: &str) {
-        eprintln!(
-            "{} {}/{}: {}",
-            Local::now().format("%Y-%m-%d %H:%M:%S"),
-            self.config.account,
-            self.mailbox,
-            msg
-        );
+        self.log_at(Level::Error, msg);
+        self.emit(Activity::Error {
+            message: msg.to_string(),
+        });
+    }
+
+    /// Add an activity of this mailbox to the activity stream, and
+    /// count it in the metrics
+    fn emit(&self, activity: Activity) {
+        metrics::record(&self.config.account, &self.mailbox, &activity);
+        activity::emit(&self.config.account, &self.mailbox, activity);
+    }
+
+    /// Update the metrics of this mailbox
+    fn count<F: FnOnce(&mut Counters)>(&self, f: F) {
+        metrics::update(&self.config.account, &self.mailbox, f);
+    }
+
+    /// Log a message that is only of interest when looking into problems
+    fn debug(&self, msg: &str) {
+        self.log_at(Level::Debug, msg);
+    }
+
+    /// Log an action that a dry run is skipping
+    fn dlog(&self, msg: &str) {
+        self.log(&format!("(dry run) Would {}", msg));
+    }
+
+    /// A trace for a new connection, if the IMAP conversation of this
+    /// mailbox is traced. `label` tells the connections apart.
+    fn trace(&self, label: &str) -> Result<Option<Trace>, String> {
+        if !self.opts.trace_imap && !self.settings.trace_imap {
+            return Ok(None);
+        }
+        Trace::open(&self.config.trace_path(Some(&self.mailbox)), label).map(Some)
     }
 
     /// Spawn a thread on this mailbox and IDLE it. When the IDLE
     /// ends, the thread will send a message to the main sync thread.
-    fn idle(&self) -> Result<JoinHandle<()>, String> {
-        let mut imap = Imap::new(&self.config)?;
-        imap.select_mailbox(&self.mailbox.as_str())?;
-        //imap.debug(true);
+    fn idle(&mut self) -> Result<JoinHandle<()>, String> {
+        let mut imap = Imap::new(&self.config, self.trace("idle")?)?;
+        self.count(|c| c.connections += 1);
+        imap.select_mailbox(self.mailbox.as_str())?;
+        self.idle_interrupter = Some(imap.interrupter()?);
         let sender = self.sender.clone();
+        let status = self.status.clone();
         let handle = spawn(move || {
-            if let Err(why) = imap.idle() {
+            if let Err(why) = imap.idle(|| status.lock().unwrap().beat()) {
                 sender.send(SyncMessage::ImapError(why)).ok();
             }
             imap.logout().ok();
@@ -99,40 +219,98 @@ impl SyncDir {
 
     /// Check if we want to IDLE this mailbox
     pub fn should_idle(&self) -> bool {
-        self.config.is_mailbox_idled(&self.mailbox)
+        !self.opts.once && self.settings.idle
     }
 
     /// Spawn a thread on this Maildir and wait for changes. On change,
     /// a message is sent to the parent the main sync thread.
-    fn fswait(&self) -> Result<JoinHandle<()>, String> {
+    /// The thread exits when the watcher is dropped.
+    fn fswait(&mut self) -> Result<JoinHandle<()>, String> {
         let sender = self.sender.clone();
-        let path = self.maildir.path();
+        let (tx, rx) = channel();
+        let mut watcher = watcher(tx, Duration::from_secs(10)).map_err(|e| e.to_string())?;
+        watcher
+            .watch(self.maildir.path(), RecursiveMode::Recursive)
+            .map_err(|e| e.to_string())?;
+        self.fswatcher = Some(watcher);
         let handle = spawn(move || {
-            let (tx, rx) = channel();
-            let mut watcher = watcher(tx, Duration::from_secs(10)).unwrap();
-            watcher.watch(path, RecursiveMode::Recursive).unwrap();
-            loop {
-                match rx.recv() {
-                    Ok(event) => {
-                        match event {
-                            notify::DebouncedEvent::Write(path) if path.is_dir() => {
-                                // trigger on dir writes only, which cover everything else
-                                sender.send(SyncMessage::MaildirChanged).ok();
-                            }
-                            _ => (),
-                        }
+            while let Ok(event) = rx.recv() {
+                match event {
+                    notify::DebouncedEvent::Write(path) if path.is_dir() => {
+                        // trigger on dir writes only, which cover everything else
+                        sender.send(SyncMessage::MaildirChanged).ok();
                     }
-                    Err(e) => {
+                    notify::DebouncedEvent::Error(e, _) => {
                         sender
                             .send(SyncMessage::MaildirError(format!("{:?}", e)))
                             .ok();
                     }
+                    _ => (),
                 }
             }
         });
         Ok(handle)
     }
 
+    /// Stop the IDLE and file system threads, and wait for them to exit.
+    fn stop_threads(&mut self) {
+        self.status.lock().unwrap().idling = false;
+        if let Some(interrupter) = self.idle_interrupter.take() {
+            interrupter.interrupt();
+        }
+        if let Some(handle) = self.idlethread.take() {
+            handle.join().ok();
+        }
+        self.fswatcher.take();
+        if let Some(handle) = self.fsthread.take() {
+            handle.join().ok();
+        }
+    }
+
+    /// Stop the IDLE and file system threads, and wait until we are
+    /// resumed. Anything that happens meanwhile is ignored, since the
+    /// sync pass after resuming catches up with it all. Returns true if
+    /// we were asked to exit meanwhile.
+    fn wait_while_paused(&mut self) -> bool {
+        self.stop_threads();
+        self.status.lock().unwrap().paused = true;
+        self.log("Paused");
+        loop {
+            match self.receiver.recv() {
+                Ok(SyncMessage::Exit) | Err(_) => return true,
+                Ok(SyncMessage::Resume) => break,
+                Ok(_) => (),
+            }
+        }
+        self.status.lock().unwrap().paused = false;
+        self.log("Resumed");
+        false
+    }
+
+    /// Wait before retrying a failed sync, so that we don't hammer the
+    /// server. Returns true if we were asked to exit meanwhile.
+    fn wait_to_retry(&mut self, delay: Duration) -> bool {
+        let deadline = Instant::now() + delay;
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            match self.receiver.recv_timeout(timeout) {
+                Ok(SyncMessage::Exit) | Err(RecvTimeoutError::Disconnected) => return true,
+                Ok(SyncMessage::Sync) => break,
+                Ok(SyncMessage::Pause) => return self.wait_while_paused(),
+                Ok(SyncMessage::ImapChanged) => {
+                    // The IDLE has ended, so start a new one on the next pass
+                    self.status.lock().unwrap().idling = false;
+                    self.idle_interrupter.take();
+                    if let Some(handle) = self.idlethread.take() {
+                        handle.join().ok();
+                    }
+                }
+                Ok(_) => (),
+                Err(RecvTimeoutError::Timeout) => break,
+            }
+        }
+        false
+    }
+
     /// Save the given message in the Maildir.
     ///
     /// Updates the cache db on success. On failure, then we will
@@ -145,7 +323,7 @@ impl SyncDir {
                 self.maildir
                     .save_message(body, &maildir_flags_from_imap(fetch.flags()))
             })
-            .and_then(|id| self.cache.add(&id, &fetch))
+            .and_then(|id| self.cache.add(&id, fetch))
     }
 
     /// Delete a given UID from the Maildir and clear its entry from cache.
@@ -158,14 +336,24 @@ impl SyncDir {
     /// appear to be a new message in the Maildir and will be resynced on
     /// next sync. This might annoy the user, but errs on the side of caution
     /// when things go wrong.
-    fn delete_message_from_maildir(&self, uid: u32) -> Result<(), String> {
+    fn delete_message_from_maildir(&mut self, uid: u32) -> Result<(), String> {
         // It is ok if we can't find the message in our maildir, it
         // may be deleted from both sides.
         match self.cache.get_uid(uid) {
+            Ok(meta) if self.opts.dry_run => {
+                self.dlog(&format!("delete UID {} ({}) from maildir", uid, meta.id()));
+                self.dry_run.local_deletes += 1;
+                Ok(())
+            }
             Ok(meta) => {
                 self.log(&format!("Deleting UID {} from maildir", uid));
                 if let Err(why) = self.maildir.delete_message(meta.id()) {
                     self.elog(&format!("Error deleting UID {}: {}", uid, why));
+                } else {
+                    self.emit(Activity::LocalDelete {
+                        uid,
+                        id: meta.id().to_string(),
+                    });
                 }
                 self.cache.delete_uid(uid)
             }
@@ -176,16 +364,57 @@ impl SyncDir {
         }
     }
 
-    /// Fetch the given UID from IMAP and save it in the Maildir.
+    /// Fetch the given UID from IMAP and save it in the Maildir. If the
+    /// message is `new`, it is passed on to the new mail command.
     ///
     /// Used to fetch new messages from the server.
-    fn cache_message_for_uid(&mut self, imap: &mut Imap, uid: Uid) -> Result<(), String> {
+    fn cache_message_for_uid(
+        &mut self,
+        imap: &mut Imap,
+        uid: Uid,
+        new: bool,
+    ) -> Result<(), String> {
+        if self.opts.dry_run {
+            if self.dry_run.downloads.insert(uid) {
+                self.dlog(&format!("download UID {}", uid));
+            }
+            return Ok(());
+        }
+
+        self.beat();
+        let started = Instant::now();
         imap.fetch_uid(uid).and_then(|zc_vec_fetch| {
             for fetch in zc_vec_fetch.deref() {
-                self.log(&format!("Fetching UID {}: {:?}", uid, fetch.flags()));
-                if let Err(e) = self.save_message_in_maildir(fetch) {
-                    return Err(format!("Save UID {} in maildir failed: {}", uid, e));
+                self.debug(&format!("Fetching UID {}: {:?}", uid, fetch.flags()));
+                let meta = self
+                    .save_message_in_maildir(fetch)
+                    .map_err(|e| format!("Save UID {} in maildir failed: {}", uid, e))?;
+                // A message that is already gone from the Maildir is not
+                // worth running the command for
+                let path = match self.config.new_mail_command {
+                    Some(_) if new => self.maildir.message_path(meta.id()),
+                    _ => None,
+                };
+                if let Some(path) = path {
+                    let mail = NewMail {
+                        account: self.config.account.clone(),
+                        mailbox: self.mailbox.clone(),
+                        uid,
+                        id: meta.id().to_string(),
+                        path,
+                        from: None,
+                        subject: None,
+                    };
+                    self.new_mail
+                        .push(mail.with_headers(fetch.body().unwrap_or_default()));
                 }
+                self.emit(Activity::Download {
+                    uid,
+                    id: meta.id().to_string(),
+                    size: meta.size(),
+                    flags: meta.flags(),
+                    duration_ms: started.elapsed().as_millis() as u64,
+                });
             }
             Ok(())
         })
@@ -210,7 +439,18 @@ impl SyncDir {
         if meta.needs_refetch(uidres) {
             // Pull down a whole new copy of the message.
             self.delete_message_from_maildir(meta.uid())?;
-            self.cache_message_for_uid(imap, meta.uid())
+            self.cache_message_for_uid(imap, meta.uid(), false)
+        } else if !self.settings.sync_flags {
+            Ok(())
+        } else if self.opts.dry_run {
+            self.dlog(&format!(
+                "update UID {} in maildir: {:?} -> {:?}",
+                uidres.uid(),
+                meta.flags(),
+                uidres.flags()
+            ));
+            self.dry_run.local_flags += 1;
+            Ok(())
         } else {
             self.log(&format!(
                 "Updating UID {}: {:?} -> {:?}",
@@ -218,17 +458,52 @@ impl SyncDir {
                 meta.flags(),
                 uidres.flags()
             ));
-            self.cache.update(uidres).and_then(|newmeta| {
-                if meta.needs_move_from_new_to_cur(uidres)
-                    && self.maildir.message_is_in_new(meta.id())?
-                {
-                    self.maildir
-                        .move_message_to_cur(meta.id(), &newmeta.flags())
-                } else {
-                    self.maildir
-                        .set_flags_for_message(newmeta.id(), &newmeta.flags())
+            // After we push flags to the server the Maildir already has
+            // them, and only the cache is behind.
+            let from = self
+                .maildir
+                .get_id(meta.id())
+                .map(|m| m.flags().to_string())
+                .unwrap_or_else(|_| meta.flags());
+            let newmeta = self.cache.update(uidres)?;
+            if meta.needs_move_from_new_to_cur(uidres)
+                && self.maildir.message_is_in_new(meta.id())?
+            {
+                self.maildir
+                    .move_message_to_cur(meta.id(), &newmeta.flags())?;
+            } else {
+                self.maildir
+                    .set_flags_for_message(newmeta.id(), &newmeta.flags())?;
+            }
+            if from != newmeta.flags() {
+                self.emit(Activity::LocalFlags {
+                    uid: uidres.uid(),
+                    id: newmeta.id().to_string(),
+                    from,
+                    to: newmeta.flags(),
+                });
+            }
+            Ok(())
+        }
+    }
+
+    /// Check if the message is larger than the max_size for this mailbox,
+    /// and should not be downloaded.
+    fn is_too_large(&mut self, uidres: &UidResult) -> bool {
+        match self.settings.max_size {
+            Some(max) if uidres.size() > max => {
+                // Only mention each message once
+                if self.skipped.insert(uidres.uid()) {
+                    self.log(&format!(
+                        "Skipping UID {}: size {} is larger than max_size {}",
+                        uidres.uid(),
+                        uidres.size(),
+                        max
+                    ));
                 }
-            })
+                true
+            }
+            _ => false,
         }
     }
 
@@ -250,15 +525,19 @@ impl SyncDir {
                     let uid = uidres.uid();
                     let res = if let Ok(meta) = self.cache.get_uid(uid) {
                         self.update_cache_for_uid(imap, &meta, &uidres)
+                    } else if self.is_too_large(&uidres) {
+                        Ok(())
                     } else {
-                        self.cache_message_for_uid(imap, uid)
+                        self.cache_message_for_uid(imap, uid, true)
                     };
                     if let Err(e) = res {
                         self.elog(&format!("Cache UID {} failed: {}", uid, e));
                         err = true;
                     }
                 }
-                FetchResult::Other(f) => self.log(&format!("Got Other FETCH response: {:?}", f)),
+                FetchResult::Other(f) => {
+                    self.log_at(Level::Trace, &format!("Got Other FETCH response: {:?}", f))
+                }
             }
         }
         if err {
@@ -312,6 +591,7 @@ impl SyncDir {
     ) -> Result<(), String> {
         let mut err = false;
         self.cache.get_known_uids().and_then(|mut cached_uids| {
+            let total = cached_uids.len();
             // Remove all the fetched uids from the cached values
             // leaving only uids that are in the cache but not on
             // the server anymore.
@@ -319,15 +599,23 @@ impl SyncDir {
                 match FetchResult::from(fetch) {
                     FetchResult::Uid(uidres) => {
                         let uid = uidres.uid();
-                        if !cached_uids.remove(&uid) {
+                        // Skipped messages and dry runs do not add new messages to the cache.
+                        if !cached_uids.remove(&uid)
+                            && !self.skipped.contains(&uid)
+                            && !self.dry_run.downloads.contains(&uid)
+                        {
                             self.elog(&format!("UID {} exists on server but not in cache", uid));
                             err = true;
                         }
                     }
-                    FetchResult::Other(f) => self.log(&format!("Got Other: {:?}", f)),
+                    FetchResult::Other(f) => {
+                        self.log_at(Level::Trace, &format!("Got Other: {:?}", f))
+                    }
                 }
             }
 
+            self.check_deletion_limit(cached_uids.len(), total, "from the maildir")?;
+
             // Remove uids from cache that have been removed on the server
             for uid in cached_uids {
                 if let Err(e) = self.delete_message_from_maildir(uid) {
@@ -344,6 +632,36 @@ impl SyncDir {
         })
     }
 
+    /// Refuse to delete `count` of `total` messages if that exceeds the deletion
+    /// limits for the account, unless deletions were confirmed on the command line.
+    ///
+    /// An empty FETCH response or an unmounted Maildir looks exactly like every
+    /// message being deleted, so we would rather stop than propagate that.
+    fn check_deletion_limit(&self, count: usize, total: usize, place: &str) -> Result<(), String> {
+        if !self.config.exceeds_deletion_limit(count, total) {
+            return Ok(());
+        }
+
+        let msg = format!(
+            "Refusing to delete {} of {} messages {}. This exceeds the deletion limit \
+             for account {}. If this is intended, rerun with --confirm-deletions.",
+            count, total, place, self.config.account
+        );
+        if self.opts.confirm_deletions {
+            self.log(&format!(
+                "Deleting {} of {} messages {}",
+                count, total, place
+            ));
+            Ok(())
+        } else if self.opts.dry_run {
+            self.elog(&format!("(dry run) {}", msg));
+            Ok(())
+        } else {
+            self.elog(&msg);
+            Err("Deletion limit exceeded".to_string())
+        }
+    }
+
     /// Perform a sync from IMAP to the cache. This updates existing cache entries,
     /// removes messages deleted on the server, and downloads new messages.
     ///
@@ -374,7 +692,11 @@ impl SyncDir {
         imap.fetch_uids(last_seen_uid + 1, None, None)
             .and_then(|zc_vec_fetch| self.cache_uids_from_imap(imap, &zc_vec_fetch))?;
 
-        self.cache.update_imap_state(mailbox)
+        if self.opts.dry_run {
+            return Ok(());
+        }
+        self.cache
+            .update_imap_state(mailbox, &self.skipped, self.settings.max_size)
     }
 
     /// Use QRESYNC to update the cache. This updates existing cache entries,
@@ -394,20 +716,51 @@ impl SyncDir {
             None
         };
 
+        // Messages skipped by an earlier pass are not among the changes
+        // since HIGHESTMODSEQ, so fetch them again if max_size changed.
+        if modseq.is_some() {
+            let (uids, max_size) = self.cache.get_skipped_uids();
+            if !uids.is_empty() && max_size != self.settings.max_size {
+                self.debug(&format!(
+                    "Checking {} skipped message(s) against the new max_size",
+                    uids.len()
+                ));
+                imap.fetch_uid_set_meta(&uids)
+                    .and_then(|zc_vec_fetch| self.cache_uids_from_imap(imap, &zc_vec_fetch))?;
+            } else {
+                self.skipped.extend(uids);
+            }
+        }
+
         imap.fetch_uids(1, None, modseq)
             .and_then(|zc_vec_fetch| self.cache_uids_from_imap(imap, &zc_vec_fetch))?;
 
-        self.check_unsolicited_for_vanished(imap).map(|vanished| {
-            for range in vanished {
-                for uid in range {
+        self.check_unsolicited_for_vanished(imap)
+            .and_then(|vanished| {
+                self.skipped
+                    .retain(|uid| !vanished.iter().any(|range| range.contains(uid)));
+                let known = self.cache.get_known_uids()?;
+                let uids = vanished
+                    .into_iter()
+                    .flatten()
+                    .filter(|uid| known.contains(uid))
+                    .collect::<Vec<u32>>();
+                self.check_deletion_limit(uids.len(), known.len(), "from the maildir")
+                    .map(|_| uids)
+            })
+            .map(|uids| {
+                for uid in uids {
                     if let Err(e) = self.delete_message_from_maildir(uid) {
                         self.elog(&format!("Error deleting UID {}: {}", uid, e));
                     }
                 }
-            }
-        })?;
+            })?;
 
-        self.cache.update_imap_state(mailbox)
+        if self.opts.dry_run {
+            return Ok(());
+        }
+        self.cache
+            .update_imap_state(mailbox, &self.skipped, self.settings.max_size)
     }
 
     /// Delete the cache of the imap state.
@@ -415,15 +768,19 @@ impl SyncDir {
     /// This is used when we have a cache validation failure, such as when
     /// the UIDVALIDITY does not match anymore.
     fn delete_imap_cache(&mut self) -> Result<(), String> {
-        self.log("Deleting Cache of all IMAP messages");
-        self.remove_uids_from_cache(
-            &self
-                .cache
-                .get_known_uids()?
-                .iter()
-                .cloned()
-                .collect::<Vec<u32>>(),
-        )
+        let uids = self
+            .cache
+            .get_known_uids()?
+            .iter()
+            .cloned()
+            .collect::<Vec<u32>>();
+        self.check_deletion_limit(uids.len(), uids.len(), "from the maildir")?;
+        if self.opts.dry_run {
+            self.dlog("delete Cache of all IMAP messages");
+        } else {
+            self.log("Deleting Cache of all IMAP messages");
+        }
+        self.remove_uids_from_cache(&uids)
     }
 
     /// Sync the Maildir with the cache. Locally deleted messages are deleted from
@@ -435,18 +792,34 @@ impl SyncDir {
     /// to the server.
     fn sync_cache_from_maildir(&mut self, imap: &mut Imap) -> Result<(), String> {
         let mut ids = self.cache.get_known_ids()?;
+        let total = ids.len();
         let (new, changed) = self.maildir.get_updates(&mut ids)?;
         let mut refetch = HashSet::<u32>::new();
 
+        self.check_deletion_limit(ids.len(), total, "from the server")?;
+
         // ids now contains maildir entries that are in the cache
         // but not on the file system anymore. They need to be deleted
         // from the server.
         for meta in ids.values() {
+            if self.opts.dry_run {
+                self.dlog(&format!(
+                    "delete UID {} ({}) from server",
+                    meta.uid(),
+                    meta.id()
+                ));
+                self.dry_run.server_deletes += 1;
+                continue;
+            }
             // delete from server
             self.log(&format!("Deleting UID {} from server", meta.uid()));
             imap.delete_uid(meta.uid())?;
             // delete from cache
             self.cache.delete_uid(meta.uid())?;
+            self.emit(Activity::ServerDelete {
+                uid: meta.uid(),
+                id: meta.id().to_string(),
+            });
             // the change will come back to us on the IDLE
             // thread, but we'll just ignore it.
         }
@@ -455,6 +828,11 @@ impl SyncDir {
         // disk than in the cache. These need to be synchronized
         // to the server.
         for id in changed {
+            // Changes are only ever to flags, see below.
+            if !self.settings.sync_flags {
+                continue;
+            }
+
             let cache_v = self.cache.get_id(&id)?;
             let mail_v = self.maildir.get_id(&id)?;
 
@@ -462,6 +840,18 @@ impl SyncDir {
             let cache_flags = SyncFlags::from(cache_v.flags().as_str());
             let maildir_flags = SyncFlags::from(mail_v.flags());
             let flags_diff = cache_flags.diff(maildir_flags);
+            if self.opts.dry_run {
+                if !flags_diff.add.empty() || !flags_diff.sub.empty() {
+                    self.dlog(&format!(
+                        "update UID {} on server: {:?} -> {:?}",
+                        cache_v.uid(),
+                        cache_v.flags(),
+                        mail_v.flags()
+                    ));
+                    self.dry_run.server_flags += 1;
+                }
+                continue;
+            }
             if let Some(flags) = flags_diff.add.as_imap_flags() {
                 imap.add_flags_for_uid(cache_v.uid(), &flags)?;
                 refetch.insert(cache_v.uid());
@@ -470,6 +860,14 @@ impl SyncDir {
                 imap.remove_flags_for_uid(cache_v.uid(), &flags)?;
                 refetch.insert(cache_v.uid());
             }
+            if refetch.contains(&cache_v.uid()) {
+                self.emit(Activity::ServerFlags {
+                    uid: cache_v.uid(),
+                    id: id.clone(),
+                    from: cache_v.flags(),
+                    to: mail_v.flags().to_string(),
+                });
+            }
 
             // If we need to push a new body.
             if cache_v.size() as u64 != mail_v.size() {
@@ -499,17 +897,25 @@ impl SyncDir {
         for id in new {
             let mail_v = self.maildir.get_id(&id)?;
             let sflags = SyncFlags::from(mail_v.flags());
-            let flags = if let Some(f) = sflags.as_imap_flags() {
-                f
-            } else {
-                Vec::new()
-            };
+            let flags = sflags.as_imap_flags().unwrap_or_default();
+
+            if self.opts.dry_run {
+                self.dlog(&format!("append {} to server: {:?}", id, flags));
+                self.dry_run.appends += 1;
+                continue;
+            }
 
             // Push to the server first, then delete the local copy
+            self.beat();
             imap.append(&fs::read(mail_v.path()).map_err(|e| e.to_string())?, &flags)?;
             // These will come back to us on the idle loop,
             // at which time they will get cache entries.
             self.maildir.delete_message(&id)?;
+            self.emit(Activity::Append {
+                id,
+                size: mail_v.size(),
+                flags: mail_v.flags().to_string(),
+            });
         }
 
         for uid in refetch {
@@ -517,9 +923,27 @@ impl SyncDir {
                 .and_then(|zc_vec_fetch| self.cache_uids_from_imap(imap, &zc_vec_fetch))?;
         }
 
+        if self.opts.dry_run {
+            return Ok(());
+        }
         self.cache.update_maildir_state()
     }
 
+    /// Log a summary of everything a dry run would have done.
+    fn log_dry_run(&self) {
+        let d = &self.dry_run;
+        self.log(&format!(
+            "(dry run) {} downloads, {} local deletions, {} local flag changes, \
+             {} server deletions, {} server flag changes, {} appends",
+            d.downloads.len(),
+            d.local_deletes,
+            d.local_flags,
+            d.server_deletes,
+            d.server_flags,
+            d.appends
+        ));
+    }
+
     /// Run loop for the sync engine. Performs a full sync then waits on change
     /// events from the IMAP server or the Maildir.
     ///
@@ -531,49 +955,73 @@ impl SyncDir {
     /// sync engine to identify new and changed elements between each set.
     fn do_sync(&mut self) -> Result<(), String> {
         loop {
-            let mut imap = Imap::new(&self.config)?;
-            //imap.debug(true);
+            self.start_pass();
+            let mut imap = Imap::new(&self.config, self.trace("sync")?)?;
+            self.count(|c| c.connections += 1);
             if imap.can_qresync() {
                 imap.enable_qresync().unwrap();
             }
-            let mailbox = imap.select_mailbox(&self.mailbox.as_str())?;
-            //imap.debug(false);
+            let mailbox = if self.opts.dry_run {
+                imap.examine_mailbox(self.mailbox.as_str())?
+            } else {
+                imap.select_mailbox(self.mailbox.as_str())?
+            };
 
             self.log(&format!(
-                "Synchronizing ({})",
-                if imap.can_qresync() { "quick" } else { "slow" }
+                "Synchronizing ({}{})",
+                if imap.can_qresync() { "quick" } else { "slow" },
+                match self.settings.direction {
+                    Direction::Pull => ", pull only",
+                    Direction::Push => ", push only",
+                    Direction::Both => "",
+                }
             ));
-            let res = if imap.can_qresync() {
+            let res = if !self.settings.direction.pulls() {
+                Ok(())
+            } else if imap.can_qresync() {
                 self.quick_sync_cache_from_imap(&mut imap, &mailbox)
-                    .and_then(|_| self.sync_cache_from_maildir(&mut imap))
-                    .and_then(|_| imap.logout())
             } else {
                 self.slow_sync_cache_from_imap(&mut imap, &mailbox)
-                    .and_then(|_| self.sync_cache_from_maildir(&mut imap))
-                    .and_then(|_| imap.logout())
-            };
+            }
+            .and_then(|_| {
+                if self.settings.direction.pushes() {
+                    self.sync_cache_from_maildir(&mut imap)
+                } else {
+                    Ok(())
+                }
+            })
+            .and_then(|_| imap.logout());
 
             self.log("Done");
+            if self.opts.dry_run {
+                self.log_dry_run();
+            }
 
             if let Err(e) = res {
                 break Err(format!("Error syncing: {}", e));
             };
+            self.finish_pass();
 
             // If we are not IDLEing, then we're done
             if !self.should_idle() {
                 break Ok(());
             }
 
-            if self.idlethread.is_none() {
+            // Server changes only matter if we pull them, and Maildir
+            // changes only matter if we push them.
+            if self.idlethread.is_none() && self.settings.direction.pulls() {
                 match self.idle() {
-                    Ok(handle) => self.idlethread = Some(handle),
+                    Ok(handle) => {
+                        self.idlethread = Some(handle);
+                        self.status.lock().unwrap().idling = true;
+                    }
                     Err(why) => {
                         break Err(format!("Error in IDLE: {}", why));
                     }
                 }
             }
 
-            if self.fsthread.is_none() {
+            if self.fsthread.is_none() && self.settings.direction.pushes() {
                 match self.fswait() {
                     Ok(handle) => self.fsthread = Some(handle),
                     Err(why) => {
@@ -584,21 +1032,31 @@ impl SyncDir {
 
             // Block until something happens
             let mut message = self.receiver.recv();
+            let mut pause = false;
 
             // Then loop over all pending messages.
             // We do this because sometimes we get multiple notifications
             // from the file system that cause unnecessary synchronization
             loop {
                 match message {
-                    Ok(SyncMessage::Exit) => return Ok(()),
+                    Ok(SyncMessage::Exit) => {
+                        self.stop_threads();
+                        return Ok(());
+                    }
+                    Ok(SyncMessage::Sync) => {
+                        self.debug("Sync requested");
+                    }
                     Ok(SyncMessage::ImapChanged) => {
-                        self.log("IMAP changed");
+                        self.debug("IMAP changed");
+                        self.count(|c| c.idle_wakeups += 1);
+                        self.status.lock().unwrap().idling = false;
+                        self.idle_interrupter.take();
                         if self.idlethread.is_some() {
                             self.idlethread.take().unwrap().join().ok();
                         }
                     }
                     Ok(SyncMessage::MaildirChanged) => {
-                        self.log("Maildir changed");
+                        self.debug("Maildir changed");
                     }
                     Ok(SyncMessage::ImapError(msg)) => {
                         self.elog(&format!("IMAP Error: {}", msg));
@@ -606,6 +1064,8 @@ impl SyncDir {
                     Ok(SyncMessage::MaildirError(msg)) => {
                         self.elog(&format!("Maildir Error: {}", msg));
                     }
+                    Ok(SyncMessage::Pause) => pause = true,
+                    Ok(SyncMessage::Resume) => pause = false,
                     Err(why) => {
                         return Err(format!("Error in recv(): {}", why));
                     }
@@ -616,19 +1076,105 @@ impl SyncDir {
                     any => message = any.map_err(|_| RecvError),
                 }
             }
+
+            if pause && self.wait_while_paused() {
+                return Ok(());
+            }
         }
     }
 
+    /// Note that we are still alive, for long sync passes.
+    fn beat(&self) {
+        self.status.lock().unwrap().beat();
+    }
+
+    /// Note in the status that a sync pass has started.
+    fn start_pass(&mut self) {
+        {
+            let mut status = self.status.lock().unwrap();
+            status.syncing = true;
+            status.beat();
+        }
+        self.pass_started = Some(Instant::now());
+        self.emit(Activity::SyncStart);
+    }
+
+    /// Run the new mail command for the messages downloaded in the sync
+    /// pass under way, if any, and add the end of the pass to the
+    /// activity stream.
+    fn end_pass(&mut self, ok: bool) {
+        let mail = std::mem::take(&mut self.new_mail);
+        match &self.config.new_mail_command {
+            Some(command) if !mail.is_empty() => {
+                self.beat();
+                self.debug(&format!(
+                    "Running new mail command for {} message(s)",
+                    mail.len()
+                ));
+                if let Err(why) = newmail::run(command, &self.maildir.path(), &mail) {
+                    self.elog(&format!("New mail command failed: {}", why));
+                }
+            }
+            _ => (),
+        }
+        if let Some(started) = self.pass_started.take() {
+            self.emit(Activity::SyncEnd {
+                ok,
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+        }
+    }
+
+    /// Note in the status that a sync pass has finished.
+    fn finish_pass(&mut self) {
+        {
+            let mut status = self.status.lock().unwrap();
+            status.syncing = false;
+            status.failing = false;
+            status.last_sync = Some(Local::now());
+        }
+        self.count(|c| {
+            c.failing = false;
+            c.last_success = Some(metrics::now());
+        });
+        self.end_pass(true);
+    }
+
+    /// Count a failed sync pass in the status.
+    fn record_error(&mut self, why: &str) {
+        {
+            let mut status = self.status.lock().unwrap();
+            status.syncing = false;
+            status.failing = true;
+            status.errors += 1;
+            status.last_error = Some(why.to_string());
+        }
+        self.count(|c| {
+            c.failing = true;
+            c.sync_failures += 1;
+        });
+        self.end_pass(false);
+    }
+
     /// Public interface for the sync engine. Runs a sync loop until it exits.
     /// If the sync loop exited with an error, then it will respawn after a
-    /// short delay.
+    /// short delay, unless we are only syncing once.
     pub fn sync(&mut self) -> Result<(), String> {
         loop {
             match self.do_sync() {
+                Err(why) if self.opts.once => {
+                    self.elog(&format!("Sync exited with error: {}", why));
+                    self.record_error(&why);
+                    break Err(why);
+                }
                 Err(why) => {
                     self.elog(&format!("Sync exited with error: {}", why));
-                    // sleep 10 to throttle retries
-                    sleep(Duration::from_secs(10));
+                    self.record_error(&why);
+                    // wait 10 to throttle retries
+                    if self.wait_to_retry(Duration::from_secs(10)) {
+                        self.stop_threads();
+                        break Ok(());
+                    }
                 }
                 Ok(_) => break Ok(()),
             }
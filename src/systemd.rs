@@ -0,0 +1,148 @@
+use crate::runner::Statuses;
+use std::env;
+use std::ffi::OsString;
+use std::os::unix::net::UnixDatagram;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{sleep, spawn};
+use std::time::{Duration, Instant};
+
+/// How often we check how things are going, if the watchdog does not
+/// need it more often.
+const TICK: Duration = Duration::from_secs(1);
+
+/// Tells systemd how we are doing when we run as a `Type=notify`
+/// service: when we are ready, a status line, and watchdog pings as long
+/// as no mailbox is stuck. See sd_notify(3).
+///
+/// This happens in a thread of its own, which only looks at the status
+/// of the mailboxes, so the watchdog is still pinged while the event
+/// loop is busy.
+pub struct Notifier {
+    socket: OsString,
+    stopping: Arc<AtomicBool>,
+}
+
+/// What the notifier thread has told systemd so far.
+struct Watch {
+    socket: OsString,
+    statuses: Statuses,
+    watchdog: Option<Duration>,
+    last_ping: Option<Instant>,
+    ready: bool,
+    status: String,
+    stuck: Vec<String>,
+}
+
+impl Notifier {
+    /// A notifier, if systemd is listening for notifications, with a
+    /// thread that keeps systemd up to date with the given statuses.
+    pub fn new(statuses: Statuses) -> Option<Notifier> {
+        let socket = env::var_os("NOTIFY_SOCKET")?;
+        let stopping = Arc::new(AtomicBool::new(false));
+        let watchdog = watchdog_interval();
+        let tick = watchdog.map_or(TICK, |interval| TICK.min(interval / 4));
+        let mut watch = Watch {
+            socket: socket.clone(),
+            statuses,
+            watchdog,
+            last_ping: None,
+            ready: false,
+            status: String::new(),
+            stuck: Vec::new(),
+        };
+        let done = stopping.clone();
+        spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                watch.update();
+                sleep(tick);
+            }
+        });
+        Some(Notifier { socket, stopping })
+    }
+
+    /// Tell systemd that we are shutting down.
+    pub fn stopping(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+        notify(&self.socket, "STOPPING=1");
+    }
+}
+
+impl Watch {
+    /// Tell systemd about anything that changed, and ping the watchdog
+    /// if it is due and every mailbox is healthy.
+    fn update(&mut self) {
+        if !self.ready && self.statuses.is_started() {
+            self.ready = true;
+            self.send("READY=1");
+        }
+
+        let status = self.statuses.summary();
+        if status != self.status {
+            self.send(&format!("STATUS={}", status));
+            self.status = status;
+        }
+
+        if let Some(interval) = self.watchdog {
+            let stuck = self.statuses.stuck();
+            if stuck != self.stuck && !stuck.is_empty() {
+                warn!(
+                    "Not pinging the systemd watchdog, because these mailboxes seem to be stuck: {}",
+                    stuck.join(", ")
+                );
+            }
+            self.stuck = stuck;
+            let due = self
+                .last_ping
+                .is_none_or(|ping| ping.elapsed() >= interval / 2);
+            if due && self.stuck.is_empty() {
+                self.send("WATCHDOG=1");
+                self.last_ping = Some(Instant::now());
+            }
+        }
+    }
+
+    fn send(&self, state: &str) {
+        notify(&self.socket, state);
+    }
+}
+
+/// Send a notification, warning if it could not be sent.
+fn notify(socket: &OsString, state: &str) {
+    if let Err(e) = send(socket, state) {
+        warn!("Could not notify systemd: {}", e);
+    }
+}
+
+/// How often systemd wants watchdog pings, if at all.
+fn watchdog_interval() -> Option<Duration> {
+    // WATCHDOG_PID is set if the watchdog is meant for another process
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(process::id()) {
+            return None;
+        }
+    }
+    let usec: u64 = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if usec == 0 {
+        return None;
+    }
+    Some(Duration::from_micros(usec))
+}
+
+/// Send a notification to the socket in `NOTIFY_SOCKET`, which is either
+/// a path, or an abstract socket name starting with `@`.
+fn send(socket: &OsString, state: &str) -> std::io::Result<()> {
+    let datagram = UnixDatagram::unbound()?;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::ffi::OsStrExt;
+        use std::os::unix::net::SocketAddr;
+        if let Some(name) = socket.as_bytes().strip_prefix(b"@") {
+            let addr = SocketAddr::from_abstract_name(name)?;
+            return datagram.send_to_addr(state.as_bytes(), &addr).map(|_| ());
+        }
+    }
+    datagram.send_to(state.as_bytes(), socket).map(|_| ())
+}